@@ -1,32 +1,360 @@
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use proto_gen::{
+    split_path_list, Artifact, BuildError, GenConfig, LogLevel, ProtoFilter, ProtoGen,
+    ProtocVersion, WellKnownTypes,
+};
+
+/// `../src/main/protobuf`, spelled component by component so it is joined with the host's
+/// separator.
+const DEFAULT_SOURCE_DIR: &[&str] = &["..", "src", "main", "protobuf"];
+const DEFAULT_OUT_DIR: &str = "proto";
+/// Module file written next to the generated code, one `pub mod` per top-level package.
+const PB_FILE: &str = "pb.rs";
+
+/// Reads a directory override from the environment, asking Cargo to re-run when it changes.
+fn env_dir(key: &str) -> Option<PathBuf> {
+    println!("cargo:rerun-if-env-changed={key}");
+    env::var_os(key).map(PathBuf::from)
+}
+
+/// Reads a path-list variable, split like `PATH` by [`split_path_list`].
+fn env_paths(key: &str) -> Option<Vec<PathBuf>> {
+    println!("cargo:rerun-if-env-changed={key}");
+    env::var_os(key).map(|list| split_path_list(&list))
+}
+
+/// The proto source roots: the `PROTO_SOURCE_DIRS` path list, else `PROTO_SOURCE_DIR`, else
+/// the default tree. Explicitly configured roots must exist.
+///
+/// `None` when nothing is configured and the default tree is missing, as in a published crate,
+/// which ships only the staged copy under [`DEFAULT_OUT_DIR`].
+fn source_dirs() -> Result<Option<Vec<PathBuf>>, BuildError> {
+    let dirs = env_paths("PROTO_SOURCE_DIRS")
+        .unwrap_or_else(|| env_dir("PROTO_SOURCE_DIR").into_iter().collect());
+    if dirs.is_empty() {
+        let default: PathBuf = DEFAULT_SOURCE_DIR.iter().collect();
+        return Ok(default.is_dir().then(|| vec![default]));
+    }
+    match dirs.iter().find(|dir| !dir.is_dir()) {
+        Some(dir) => Err(BuildError::SourceMissing(dir.clone())),
+        None => Ok(Some(dirs)),
+    }
+}
+
+/// Where generated Rust lands: `PROTO_GENERATED_DIR` when set, Cargo's `OUT_DIR` otherwise.
+fn out_dir() -> Result<PathBuf, BuildError> {
+    env_dir("PROTO_GENERATED_DIR")
+        .or_else(|| env::var_os("OUT_DIR").map(PathBuf::from))
+        .ok_or_else(|| BuildError::Config("OUT_DIR is not set".to_string()))
+}
+
+/// Reads a boolean switch from the environment, falling back to `default` when unset.
+fn env_flag(key: &str, default: bool) -> Result<bool, BuildError> {
+    println!("cargo:rerun-if-env-changed={key}");
+    match env::var(key) {
+        Err(_) => Ok(default),
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(BuildError::Config(format!(
+                "{key} must be `true` or `false`, got `{value}`"
+            ))),
+        },
+    }
+}
+
+/// Reads a comma-separated list from the environment, ignoring blank entries.
+fn env_list(key: &str) -> Vec<String> {
+    println!("cargo:rerun-if-env-changed={key}");
+    env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses one `PROTO_EXTERN_PATHS` entry of the form `.my.package=::common_crate::pb`.
+fn parse_extern_path(entry: &str) -> Result<(String, String), BuildError> {
+    match entry.split_once('=') {
+        Some((proto_path, rust_path))
+            if proto_path.trim().starts_with('.')
+                && !rust_path.trim().is_empty()
+                && !rust_path.contains('=') =>
+        {
+            Ok((proto_path.trim().to_string(), rust_path.trim().to_string()))
+        }
+        _ => Err(BuildError::Config(format!(
+            "invalid PROTO_EXTERN_PATHS entry `{entry}`, expected `.proto.package=::rust::path`"
+        ))),
+    }
+}
+
+/// Parses one `PROTO_MODULE_MAP` entry of the form `com.acme.billing.v1=billing`.
+fn parse_module_mapping(entry: &str) -> Result<(String, String), BuildError> {
+    match entry.split_once('=') {
+        Some((package, module)) if !package.trim().is_empty() && !module.trim().is_empty() => {
+            Ok((package.trim().to_string(), module.trim().to_string()))
+        }
+        _ => Err(BuildError::Config(format!(
+            "invalid PROTO_MODULE_MAP entry `{entry}`, expected `proto.package=rust::module`"
+        ))),
+    }
+}
+
+/// Writes [`PB_FILE`] into Cargo's `OUT_DIR`, wrapping each top-level module generated into
+/// `generated_dir` so the crate can pull everything in with a single `include!`. It never goes
+/// into `generated_dir` itself, which may be a committed vendor directory that builds must
+/// leave untouched, so the inner includes are absolute. Unchanged contents are not rewritten.
+fn write_pb(generated_dir: &Path, modules: &[String]) -> Result<PathBuf, BuildError> {
+    let generated_dir = std::path::absolute(generated_dir)?;
+    let mut pb = String::from("// @generated by build.rs. Do not edit.\n");
+    for module in modules {
+        let file = generated_dir.join(proto_gen::module_file_name(module));
+        pb.push_str(&format!(
+            "pub mod {module} {{\n    include!({:?});\n}}\n",
+            file.display().to_string()
+        ));
+    }
+    let out_dir = env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| BuildError::Config("OUT_DIR is not set".to_string()))?;
+    let path = out_dir.join(PB_FILE);
+    if fs::read_to_string(&path).ok().as_deref() != Some(pb.as_str()) {
+        fs::write(&path, pb)?;
+    }
+    Ok(path)
+}
+
+/// Hands the path of [`PB_FILE`] to the crate as `PROTO_PB_RS`, so src/lib.rs can `include!` it
+/// whether the generated code went to `OUT_DIR`, `PROTO_GENERATED_DIR` or `PROTO_VENDOR_DIR`,
+/// and sets `proto_module = "<name>"` for each module in it so re-exports can depend on them.
+fn emit_pb_location(pb: &Path, modules: &[String]) -> Result<(), BuildError> {
+    println!(
+        "cargo:rustc-env=PROTO_PB_RS={}",
+        std::path::absolute(pb)?.display()
+    );
+    println!("cargo:rustc-check-cfg=cfg(proto_module, values(any()))");
+    for module in modules {
+        println!("cargo:rustc-cfg=proto_module=\"{module}\"");
+    }
+    Ok(())
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let source_dir = Path::new("../src/main/protobuf");
-    let dest_dir = Path::new("proto");
-
-    fs::create_dir_all(dest_dir)?;
-
-    if source_dir.exists() {
-        for entry in fs::read_dir(source_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().map_or(false, |ext| ext == "proto") {
-                let file_name = path.file_name().unwrap();
-                let dest_path = dest_dir.join(file_name);
-                fs::copy(&path, &dest_path)?;
-                println!("cargo:rerun-if-changed={}", path.display());
-            }
-        }
-    }
-
-    // Step 2: Generate Rust code from proto files
-    tonic_prost_build::configure()
-        .build_server(true)
-        .build_client(true)
-        .compile_protos(
-            &["proto/hello.proto"],
-            &["proto"],
-        )?;
+    // Box the rendered message so Cargo shows the readable `Display` form, not `Debug`.
+    run().map_err(|err| err.to_string().into())
+}
+
+fn run() -> Result<(), BuildError> {
+    println!("cargo:rerun-if-env-changed=PROTO_LOG");
+    // `info` adds an end-of-build summary to the counts, `debug` every decision along the way.
+    let log_level: LogLevel = env::var("PROTO_LOG").unwrap_or_default().parse()?;
+    proto_gen::set_log_level(log_level);
+
+    // A vendor directory replaces the generated dir. It is meant to be committed, so consumers
+    // building from it never need protoc while its checksum headers match the protos.
+    let vendor_dir = env_dir("PROTO_VENDOR_DIR");
+    let out_dir = match &vendor_dir {
+        Some(dir) => dir.clone(),
+        None => out_dir()?,
+    };
+
+    // Both stub halves come from the `server` and `client` Cargo features, which are on by
+    // default; with neither enabled only the message types are generated. The `grpc-web`
+    // feature (or PROTO_GRPC_WEB) shapes the client for browsers.
+    let build_client = env::var_os("CARGO_FEATURE_CLIENT").is_some();
+    let grpc_web = env_flag(
+        "PROTO_GRPC_WEB",
+        env::var_os("CARGO_FEATURE_GRPC_WEB").is_some(),
+    )?;
+    let client_helpers = env_flag("PROTO_GEN_CLIENT_HELPERS", false)?;
+    // Plain prost for protos used only for serialization; no tonic codegen is involved.
+    let messages_only = env_flag("PROTO_MESSAGES_ONLY", false)?;
+    // proto-gen only writes the helpers for tonic's transport clients, and
+    // tests/client_helpers.rs only compiles against them.
+    println!("cargo:rustc-check-cfg=cfg(proto_client_helpers)");
+    if client_helpers && build_client && !grpc_web && !messages_only {
+        println!("cargo:rustc-cfg=proto_client_helpers");
+    }
+    // Only needed with protoc older than 3.15, which rejects proto3 `optional` without it. The
+    // generated code is the same either way.
+    let proto3_optional = env_flag("PROTO_DEFAULT_OPTIONAL", false)?;
+    let mut builder = ProtoGen::new()
+        .out_dir(&out_dir)
+        .proto3_optional(proto3_optional)
+        .doc_comments(env_flag("PROTO_DOC_COMMENTS", true)?)
+        .timing(env_flag("PROTO_TIMING", false)?)
+        .filter(ProtoFilter::new(
+            &env_list("PROTO_INCLUDE_GLOB"),
+            &env_list("PROTO_EXCLUDE_GLOB"),
+        )?)
+        .allow_empty(env_flag("PROTO_ALLOW_EMPTY", true)?)
+        .dry_run(env_flag("PROTO_DRY_RUN", false)?)
+        .clean(env_flag("PROTO_CLEAN", false)?)
+        .cache(!env_flag("PROTO_NO_CACHE", false)?)
+        .incremental(env_flag("PROTO_INCREMENTAL", false)?)
+        .vendor(vendor_dir.is_some())
+        .build_server(env::var_os("CARGO_FEATURE_SERVER").is_some())
+        .build_client(build_client)
+        .grpc_web(grpc_web)
+        .client_helpers(client_helpers)
+        .messages_only(messages_only);
+    let (source_dirs, copy) = match source_dirs()? {
+        Some(dirs) => (dirs, env_flag("PROTO_COPY", true)?),
+        // Without the source tree the shipped copy is all there is, so it is compiled in place.
+        None => (vec![PathBuf::from(DEFAULT_OUT_DIR)], false),
+    };
+    for dir in source_dirs {
+        builder = builder.source_dir(dir);
+    }
+    // PROTO_FILE_LIST=protos.txt compiles only the protos it names, one per line relative to a
+    // source dir, instead of everything discovered there.
+    if let Some(list) = env_dir("PROTO_FILE_LIST") {
+        builder = builder.file_list(list);
+    }
+    // With copying disabled the sources are compiled in place.
+    if copy {
+        builder = builder.staging_dir(
+            env_dir("PROTO_OUT_DIR").unwrap_or_else(|| PathBuf::from(DEFAULT_OUT_DIR)),
+        );
+    }
+    // PROTO_STRIP_PREFIX=com/acme stages `com/acme/billing/v1/x.proto` as `billing/v1/x.proto`.
+    if let Some(prefix) = env_dir("PROTO_STRIP_PREFIX") {
+        builder = builder.strip_prefix(prefix);
+    }
+    for dir in env_paths("PROTO_INCLUDE_PATHS").unwrap_or_default() {
+        builder = builder.include_path(dir);
+    }
+    if env_flag("PROTO_RUSTFMT", false)? {
+        // RUSTFMT picks a specific binary, the same override rustfmt-aware tools honour.
+        builder = builder.rustfmt(env_dir("RUSTFMT").unwrap_or_else(|| PathBuf::from("rustfmt")));
+    }
+
+    // The derive sits behind the crate's `serde` feature so the generated code still compiles
+    // for consumers that don't pull serde in.
+    if env_flag("PROTO_SERDE", false)? {
+        let mut paths = env_list("PROTO_SERDE_PATHS");
+        if paths.is_empty() {
+            paths.push(".".to_string());
+        }
+        for path in paths {
+            builder = builder.type_attribute(
+                path,
+                r#"#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]"#,
+            );
+        }
+    }
+
+    let config_path = match env_dir("PROTO_CONFIG") {
+        Some(path) if !path.is_file() => {
+            return Err(BuildError::Config(format!(
+                "PROTO_CONFIG `{}` does not exist",
+                path.display()
+            )));
+        }
+        Some(path) => path,
+        None => PathBuf::from(proto_gen::CONFIG_FILE),
+    };
+    // Cargo treats a missing rerun-if-changed path as always dirty, so only watch a real file.
+    if config_path.is_file() {
+        println!("cargo:rerun-if-changed={}", config_path.display());
+    }
+    builder = builder.gen_config(GenConfig::load(&config_path)?);
+    for entry in env_list("PROTO_MODULE_MAP") {
+        let (package, module) = parse_module_mapping(&entry)?;
+        builder = builder.module_map(package, module);
+    }
+
+    // In native mode the shim written to the out dir must be included at PROTO_WKT_MODULE.
+    println!("cargo:rerun-if-env-changed=PROTO_WKT");
+    println!("cargo:rerun-if-env-changed=PROTO_WKT_MODULE");
+    builder = builder.well_known_types(match env::var("PROTO_WKT").as_deref() {
+        Err(_) | Ok("") => WellKnownTypes::Default,
+        Ok("prost-types") => WellKnownTypes::ProstTypes,
+        Ok("native") => WellKnownTypes::Native {
+            module: env::var("PROTO_WKT_MODULE").unwrap_or_else(|_| "crate::wkt".to_string()),
+        },
+        Ok(other) => {
+            return Err(BuildError::Config(format!(
+                "PROTO_WKT must be `prost-types` or `native`, got `{other}`"
+            )));
+        }
+    });
+
+    for entry in env_list("PROTO_EXTERN_PATHS") {
+        let (proto_path, rust_path) = parse_extern_path(&entry)?;
+        builder = builder.extern_path(proto_path, rust_path);
+    }
+
+    // Relative descriptor paths land in OUT_DIR so they can be embedded with `include_bytes!`.
+    if let Some(path) = env_dir("PROTO_DESCRIPTOR_SET") {
+        let out_dir = env::var_os("OUT_DIR")
+            .ok_or_else(|| BuildError::Config("OUT_DIR is not set".to_string()))?;
+        let path = PathBuf::from(out_dir).join(path);
+        println!("cargo:rustc-env=PROTO_DESCRIPTOR_PATH={}", path.display());
+        builder = builder.descriptor_set(path);
+    }
+
+    // Crates sharing PROTO_OUT_DIR or PROTO_GENERATED_DIR take turns; this bounds the wait.
+    println!("cargo:rerun-if-env-changed=PROTO_LOCK_TIMEOUT");
+    if let Ok(secs) = env::var("PROTO_LOCK_TIMEOUT") {
+        let secs = secs.trim().parse().map_err(|_| {
+            BuildError::Config(format!(
+                "PROTO_LOCK_TIMEOUT must be a number of seconds, got `{secs}`"
+            ))
+        })?;
+        builder = builder.lock_timeout(Duration::from_secs(secs));
+    }
+
+    println!("cargo:rerun-if-env-changed=PROTO_MIN_PROTOC_VERSION");
+    match env::var("PROTO_MIN_PROTOC_VERSION") {
+        Ok(version) if !version.trim().is_empty() => {
+            builder =
+                builder.min_protoc_version(version.parse::<ProtocVersion>().map_err(|_| {
+                    BuildError::Config(format!(
+                        "PROTO_MIN_PROTOC_VERSION must look like `3.21.0`, got `{version}`"
+                    ))
+                })?);
+        }
+        _ => {}
+    }
+
+    // CI runs this over the committed vendor directory to catch protos edited without
+    // regenerating; it compares fresh output with the directory and never writes to it.
+    if env_flag("PROTO_CHECK", false)? {
+        if vendor_dir.is_none() {
+            return Err(BuildError::Config(
+                "PROTO_CHECK compares against PROTO_VENDOR_DIR, which is not set".to_string(),
+            ));
+        }
+        // Once the check passes, the run below finds the code current and leaves it alone.
+        builder.check()?;
+    }
+
+    let mut manifest = builder.run()?;
+    // Written even when nothing was generated, so the crate's `include!` always resolves.
+    let pb = write_pb(&out_dir, &manifest.modules)?;
+    emit_pb_location(&pb, &manifest.modules)?;
+    // Nothing was generated: an empty proto set or a dry run.
+    if manifest.outputs.is_empty() {
+        return Ok(());
+    }
+    manifest.outputs.push(Artifact {
+        size: fs::metadata(&pb)?.len(),
+        path: pb,
+    });
+
+    if let Some(path) = env_dir("PROTO_MANIFEST") {
+        manifest.write(&path)?;
+    }
     Ok(())
 }
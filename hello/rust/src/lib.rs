@@ -1,3 +1,5 @@
 pub mod proto {
-    tonic::include_proto!("hello");
-}
\ No newline at end of file
+    include!(env!("PROTO_PB_RS"));
+    #[cfg(proto_module = "hello")]
+    pub use self::hello::*;
+}
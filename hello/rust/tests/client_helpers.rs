@@ -0,0 +1,14 @@
+//! Only built when PROTO_GEN_CLIENT_HELPERS=true adds `connect` helpers next to the client
+//! stubs, which also needs the `client` feature and no grpc-web.
+#![cfg(proto_client_helpers)]
+
+use hello::proto::hello_service;
+use hello::proto::hello_service_client::HelloServiceClient;
+use tonic::transport::{Channel, Error};
+
+#[test]
+fn connect_helper_returns_the_generated_client() {
+    let connect = hello_service::connect("http://[::1]:50051");
+    // Naming the output type checks the helper against the stub; the future is never polled.
+    let _: &dyn std::future::Future<Output = Result<HelloServiceClient<Channel>, Error>> = &connect;
+}
@@ -0,0 +1,36 @@
+//! Checks the generated stubs against the Cargo features the crate was built with. Run it with
+//! `--no-default-features --features client` too, to see a client-only build lose the server.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// The generated code, read through the chain of `include!`s that starts at pb.rs.
+fn generated_source() -> String {
+    let mut source = String::new();
+    let mut pending = vec![PathBuf::from(env!("PROTO_PB_RS"))];
+    while let Some(path) = pending.pop() {
+        let text = fs::read_to_string(&path).unwrap();
+        for line in text.lines() {
+            let included = line.trim().strip_prefix("include!(\"");
+            if let Some(included) = included.and_then(|rest| rest.strip_suffix("\");")) {
+                pending.push(path.parent().unwrap().join(included));
+            }
+        }
+        source.push_str(&text);
+    }
+    source
+}
+
+#[test]
+fn stub_modules_follow_the_server_and_client_features() {
+    let generated = generated_source();
+    assert!(generated.contains("pub struct HelloRequest"));
+    assert_eq!(
+        generated.contains("pub mod hello_service_server"),
+        cfg!(feature = "server")
+    );
+    assert_eq!(
+        generated.contains("pub mod hello_service_client"),
+        cfg!(feature = "client")
+    );
+}
@@ -0,0 +1,38 @@
+//! Only built with the `grpc-web` feature, whose client stubs drop tonic's transport and run
+//! over any HTTP service, such as tonic-web's.
+#![cfg(feature = "grpc-web")]
+
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::task::{Context, Poll};
+
+use hello::proto::hello_service_client::HelloServiceClient;
+use hello::proto::HelloRequest;
+use tonic::body::Body;
+use tonic::codegen::{http, Service};
+use tonic_web::GrpcWebClientService;
+
+/// Stands in for the browser's fetch or any other HTTP client; only its types are exercised.
+#[derive(Clone)]
+struct StubHttp;
+
+impl<B> Service<http::Request<B>> for StubHttp {
+    type Response = http::Response<Body>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: http::Request<B>) -> Self::Future {
+        ready(Ok(http::Response::new(Body::empty())))
+    }
+}
+
+#[test]
+fn client_builds_over_a_grpc_web_service() {
+    let mut client = HelloServiceClient::new(GrpcWebClientService::new(StubHttp));
+    // Creating the call type-checks the whole request path; it is never polled.
+    drop(client.say_hello(HelloRequest::default()));
+}
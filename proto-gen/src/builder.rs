@@ -0,0 +1,755 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use crate::lock::{self, DEFAULT_LOCK_TIMEOUT};
+use crate::vendor::{self, ScratchDir};
+use crate::{
+    clean_stale, compile_protos_with, discover_protos, import_graph, log, parse,
+    plan_copies_stripped, resolve_out_dir, stage_protos, staging_dirs, track_dependencies,
+    validate_protos, BuildError, BuildManifest, CompileConfig, FileConfig, GenConfig, LogLevel,
+    ProtoFilter, ProtocVersion, WellKnownTypes,
+};
+
+/// Typed front end for the whole pipeline: discover, validate, stage and compile.
+///
+/// ```no_run
+/// # fn main() -> Result<(), proto_gen::BuildError> {
+/// let manifest = proto_gen::ProtoGen::new()
+///     .source_dir("protos")
+///     .staging_dir("target/proto")
+///     .build_client(false)
+///     .type_attribute(".", "#[derive(Eq, Hash)]")
+///     .run()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Everything [`run`](ProtoGen::run) writes besides the staged protos (generated code, module
+/// indexes, the compile cache) lives in the out dir, so one build script can call it several
+/// times with different out dirs, for example to generate server and client stubs separately.
+#[derive(Debug, Clone)]
+pub struct ProtoGen {
+    source_dirs: Vec<PathBuf>,
+    staging_dir: Option<PathBuf>,
+    strip_prefix: PathBuf,
+    include_paths: Vec<PathBuf>,
+    filter: ProtoFilter,
+    file_list: Option<PathBuf>,
+    allow_empty: bool,
+    clean: bool,
+    dry_run: bool,
+    min_protoc_version: Option<ProtocVersion>,
+    lock_timeout: Duration,
+    timing: bool,
+    config: CompileConfig,
+}
+
+impl Default for ProtoGen {
+    fn default() -> Self {
+        ProtoGen {
+            source_dirs: Vec::new(),
+            staging_dir: None,
+            strip_prefix: PathBuf::new(),
+            include_paths: Vec::new(),
+            filter: ProtoFilter::default(),
+            file_list: None,
+            allow_empty: true,
+            clean: false,
+            dry_run: false,
+            min_protoc_version: None,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            timing: false,
+            config: CompileConfig::default(),
+        }
+    }
+}
+
+impl ProtoGen {
+    /// Starts from the defaults: both stub halves, caching on, empty proto sets allowed.
+    pub fn new() -> Self {
+        ProtoGen::default()
+    }
+
+    /// Adds a root to discover protos under. Each root is also an include path, so imports
+    /// between roots resolve.
+    pub fn source_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.source_dirs.push(dir.into());
+        self
+    }
+
+    /// Mirrors the protos into `dir` before compiling them, with one subdirectory per root when
+    /// there are several. Without a staging dir the sources are compiled in place.
+    pub fn staging_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.staging_dir = Some(dir.into());
+        self
+    }
+
+    /// Drops a leading directory such as `com/acme` from every staged path, rewriting imports
+    /// under it to match. Only applies together with a staging dir.
+    pub fn strip_prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.strip_prefix = prefix.into();
+        self
+    }
+
+    /// Directory receiving the generated Rust; Cargo's `OUT_DIR` by default.
+    pub fn out_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.out_dir = Some(dir.into());
+        self
+    }
+
+    /// Adds a directory that imports are resolved against but whose protos aren't compiled.
+    pub fn include_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_paths.push(dir.into());
+        self
+    }
+
+    /// Narrows the discovered protos with include/exclude globs.
+    pub fn filter(mut self, filter: ProtoFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Compiles the protos named in `list` instead of discovering every proto under the source
+    /// dirs. Each line names a file relative to one of them, the first that holds it; blank
+    /// lines and lines starting with `#` are skipped, and an entry that isn't found is an error.
+    /// The source dirs stay include paths, so unlisted protos can still be imported, and the
+    /// include/exclude globs still apply.
+    pub fn file_list(mut self, list: impl Into<PathBuf>) -> Self {
+        self.file_list = Some(list.into());
+        self
+    }
+
+    /// Whether finding no protos at all is fine (the default) or an error.
+    pub fn allow_empty(mut self, allow: bool) -> Self {
+        self.allow_empty = allow;
+        self
+    }
+
+    /// Remove staged protos whose source has gone away.
+    pub fn clean(mut self, clean: bool) -> Self {
+        self.clean = clean;
+        self
+    }
+
+    /// Report what would be copied and compiled without writing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Fail unless protoc is at least `version`.
+    pub fn min_protoc_version(mut self, version: ProtocVersion) -> Self {
+        self.min_protoc_version = Some(version);
+        self
+    }
+
+    /// How long to wait for another build writing the same staging or out dir.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Whether to report how long discovery, validation, copying and compiling took, as build
+    /// warnings once the run is over (off by default).
+    pub fn timing(mut self, enable: bool) -> Self {
+        self.timing = enable;
+        self
+    }
+
+    /// Generate the gRPC server stubs (the default).
+    pub fn build_server(mut self, enable: bool) -> Self {
+        self.config.build_server = enable;
+        self
+    }
+
+    /// Generate the gRPC client stubs (the default).
+    pub fn build_client(mut self, enable: bool) -> Self {
+        self.config.build_client = enable;
+        self
+    }
+
+    /// Generate client stubs usable over gRPC-Web; see [`CompileConfig::grpc_web`].
+    pub fn grpc_web(mut self, enable: bool) -> Self {
+        self.config.grpc_web = enable;
+        self
+    }
+
+    /// Generate `connect` helpers for the client stubs; see [`CompileConfig::client_helpers`].
+    pub fn client_helpers(mut self, enable: bool) -> Self {
+        self.config.client_helpers = enable;
+        self
+    }
+
+    /// Generate only message types, with plain `prost_build`; see
+    /// [`CompileConfig::messages_only`].
+    pub fn messages_only(mut self, enable: bool) -> Self {
+        self.config.messages_only = enable;
+        self
+    }
+
+    /// Let protoc older than 3.15 accept `optional` proto3 fields; see
+    /// [`CompileConfig::proto3_optional`].
+    pub fn proto3_optional(mut self, enable: bool) -> Self {
+        self.config.proto3_optional = enable;
+        self
+    }
+
+    /// Whether proto comments become doc comments (the default); see
+    /// [`CompileConfig::doc_comments`].
+    pub fn doc_comments(mut self, enable: bool) -> Self {
+        self.config.doc_comments = enable;
+        self
+    }
+
+    /// Puts `attribute` on the message or enum at the proto `path`, such as `.billing.Invoice`,
+    /// or on every type with `.`.
+    pub fn type_attribute(mut self, path: impl Into<String>, attribute: impl Into<String>) -> Self {
+        self.config
+            .type_attributes
+            .push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Puts `attribute` on the field at the proto `path`, such as `.billing.Invoice.total`.
+    pub fn field_attribute(
+        mut self,
+        path: impl Into<String>,
+        attribute: impl Into<String>,
+    ) -> Self {
+        self.config
+            .field_attributes
+            .push((path.into(), attribute.into()));
+        self
+    }
+
+    /// Stores the field at the proto `path` behind a `Box`; see [`CompileConfig::boxed`].
+    pub fn boxed(mut self, path: impl Into<String>) -> Self {
+        self.config.boxed.push(path.into());
+        self
+    }
+
+    /// Uses the existing Rust type at `rust_path` for the proto `proto_path` instead of
+    /// generating one, as prost does for `.google.protobuf`.
+    pub fn extern_path(
+        mut self,
+        proto_path: impl Into<String>,
+        rust_path: impl Into<String>,
+    ) -> Self {
+        self.config
+            .extern_paths
+            .push((proto_path.into(), rust_path.into()));
+        self
+    }
+
+    /// Re-exports the code for `package` under the module path `module`; see
+    /// [`CompileConfig::module_map`].
+    pub fn module_map(mut self, package: impl Into<String>, module: impl Into<String>) -> Self {
+        self.config.module_map.push((package.into(), module.into()));
+        self
+    }
+
+    /// Applies every entry of a loaded `proto-gen.toml`.
+    pub fn gen_config(mut self, gen_config: GenConfig) -> Self {
+        for entry in gen_config.type_attribute {
+            self = self.type_attribute(entry.path, entry.attribute);
+        }
+        for entry in gen_config.field_attribute {
+            self = self.field_attribute(entry.path, entry.attribute);
+        }
+        for entry in gen_config.boxed_field {
+            self = self.boxed(entry.path);
+        }
+        for entry in gen_config.module_map {
+            self = self.module_map(entry.package, entry.module);
+        }
+        self
+    }
+
+    /// How the `google.protobuf` well-known types are represented; see [`WellKnownTypes`].
+    pub fn well_known_types(mut self, well_known_types: WellKnownTypes) -> Self {
+        self.config.well_known_types = well_known_types;
+        self
+    }
+
+    /// Writes the encoded `FileDescriptorSet` to `path`, for server reflection and the like.
+    pub fn descriptor_set(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.descriptor_set = Some(path.into());
+        self
+    }
+
+    /// Whether to skip protoc when nothing changed since the last compile (the default); see
+    /// [`CompileConfig::cache`].
+    pub fn cache(mut self, enable: bool) -> Self {
+        self.config.cache = enable;
+        self
+    }
+
+    /// Compile only the protos a change affects; see [`CompileConfig::incremental`].
+    pub fn incremental(mut self, enable: bool) -> Self {
+        self.config.incremental = enable;
+        self
+    }
+
+    /// Formats the generated code with the `rustfmt` binary at this path; one that is missing
+    /// only produces a warning.
+    pub fn rustfmt(mut self, rustfmt: impl Into<PathBuf>) -> Self {
+        self.config.rustfmt = Some(rustfmt.into());
+        self
+    }
+
+    /// Treat the out dir as a committed vendor directory; see [`CompileConfig::vendor`].
+    pub fn vendor(mut self, enable: bool) -> Self {
+        self.config.vendor = enable;
+        self
+    }
+
+    /// Runs the pipeline and describes what it produced.
+    ///
+    /// An empty proto set (when allowed) yields an empty manifest, and a dry run lists the
+    /// source protos without any outputs.
+    pub fn run(&self) -> Result<BuildManifest, BuildError> {
+        let mut timings = Timings::default();
+        let result = self.run_timed(&mut timings);
+        if self.timing {
+            timings.print();
+        }
+        result
+    }
+
+    /// Regenerates into a scratch directory and compares the result with the out dir, which is
+    /// left untouched, failing with [`BuildError::VendorOutdated`] if any generated file differs
+    /// beyond its checksum header. Meant for CI over a committed vendor directory.
+    ///
+    /// The scratch directory lives under Cargo's `OUT_DIR` (the system temp dir outside a build
+    /// script) and is removed again whatever the outcome.
+    pub fn check(&self) -> Result<(), BuildError> {
+        let vendor_dir = resolve_out_dir(&self.config)?;
+        let scratch = ScratchDir::create(
+            env::var_os("OUT_DIR")
+                .map_or_else(env::temp_dir, PathBuf::from)
+                .join(format!("proto-gen-check-{}", process::id())),
+        )?;
+        let mut fresh = self.clone();
+        fresh.config.out_dir = Some(scratch.path().to_path_buf());
+        fresh.config.cache = false;
+        fresh.config.incremental = false;
+        // A descriptor set may be committed as well, so it goes to the scratch dir too.
+        if let Some(name) = self
+            .config
+            .descriptor_set
+            .as_ref()
+            .and_then(|p| p.file_name())
+        {
+            fresh.config.descriptor_set = Some(scratch.path().join(name));
+        }
+        let manifest = fresh.run()?;
+        let files: Vec<PathBuf> = manifest
+            .outputs
+            .into_iter()
+            .map(|artifact| artifact.path)
+            .collect();
+        let differences = vendor::compare(scratch.path(), &files, &vendor_dir)?;
+        if differences.is_empty() {
+            return Ok(());
+        }
+        for difference in &differences {
+            println!("cargo:warning={}: {difference}", vendor_dir.display());
+        }
+        Err(BuildError::VendorOutdated(vendor_dir, differences))
+    }
+
+    fn run_timed(&self, timings: &mut Timings) -> Result<BuildManifest, BuildError> {
+        if self.source_dirs.is_empty() {
+            return Err(BuildError::Config(
+                "no proto source directory configured".to_string(),
+            ));
+        }
+        let dest_dirs = match &self.staging_dir {
+            Some(dir) => staging_dirs(dir, &self.source_dirs)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => vec![None; self.source_dirs.len()],
+        };
+        // tonic-prost-build is pinned to a release that can leave out the transport, so the only
+        // way this setting goes unused is without client stubs.
+        if self.config.grpc_web && !self.config.build_client {
+            println!("cargo:warning=gRPC-Web only affects client stubs, which are not being built");
+        }
+        if self.config.client_helpers && self.config.build_client && self.config.grpc_web {
+            println!(
+                "cargo:warning=client helpers need tonic's transport, which gRPC-Web stubs leave out; skipping them"
+            );
+        }
+        if self.staging_dir.is_none() && !self.strip_prefix.as_os_str().is_empty() {
+            println!(
+                "cargo:warning=strip prefix `{}` is ignored when protos are compiled in place",
+                self.strip_prefix.display()
+            );
+        }
+
+        // A file reachable from two overlapping roots is only compiled from the first of them.
+        let started = Instant::now();
+        let mut listed = match &self.file_list {
+            Some(list) => listed_protos(list, &self.source_dirs)?
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => vec![None; self.source_dirs.len()],
+        };
+        let mut seen = HashSet::new();
+        let mut roots = Vec::with_capacity(self.source_dirs.len());
+        let mut found = 0;
+        // Include globs that have matched nothing under any root so far.
+        let mut unmatched: Option<Vec<String>> = None;
+        for ((source_dir, dest_dir), listed) in
+            self.source_dirs.iter().zip(dest_dirs).zip(&mut listed)
+        {
+            let discovered = match listed.take() {
+                Some(listed) => listed,
+                None => discover_protos(source_dir)?,
+            };
+            let count = discovered.len();
+            let (selected, unmatched_here) = self.filter.apply(source_dir, discovered);
+            unmatched = Some(match unmatched {
+                Some(previous) => previous
+                    .into_iter()
+                    .filter(|glob| unmatched_here.contains(glob))
+                    .collect(),
+                None => unmatched_here,
+            });
+            let selected: Vec<PathBuf> = selected
+                .into_iter()
+                .filter(|path| seen.insert(path.clone()))
+                .collect();
+            log(
+                LogLevel::Info,
+                format_args!(
+                    "discovered {count} proto(s) under {}, {} selected",
+                    source_dir.display(),
+                    selected.len()
+                ),
+            );
+            found += count;
+            roots.push((source_dir.as_path(), dest_dir, selected));
+        }
+        let discovered: Vec<PathBuf> = roots
+            .iter()
+            .flat_map(|(_, _, protos)| protos.iter().cloned())
+            .collect();
+        timings.discovery = Some(started.elapsed());
+        if found > 0 {
+            for glob in unmatched.unwrap_or_default() {
+                println!(
+                    "cargo:warning=include glob `{glob}` matches no proto under {}",
+                    display_dirs(&self.source_dirs)
+                );
+            }
+        }
+        if discovered.is_empty() && found > 0 {
+            println!(
+                "cargo:warning=include/exclude globs filtered out all {found} proto(s) under {}",
+                display_dirs(&self.source_dirs)
+            );
+        }
+        if discovered.is_empty() {
+            if !self.allow_empty {
+                return Err(BuildError::EmptyProtoSet(self.source_dirs.clone()));
+            }
+            println!(
+                "cargo:warning=no .proto files found under {}, skipping code generation",
+                display_dirs(&self.source_dirs)
+            );
+            return Ok(BuildManifest::default());
+        }
+        for (source_dir, dest_dir, protos) in &roots {
+            track_dependencies(
+                source_dir,
+                protos,
+                dest_dir.as_deref().unwrap_or(source_dir),
+            );
+        }
+        // Protos imported from the include paths shape the generated code just as much.
+        for dir in &self.include_paths {
+            println!("cargo:rerun-if-changed={}", dir.display());
+        }
+
+        let started = Instant::now();
+        if let Err(errors) = validate_protos(&discovered) {
+            for error in &errors {
+                println!("cargo:warning={error}");
+            }
+            return Err(BuildError::Invalid(errors));
+        }
+        let mut includes = self.source_dirs.clone();
+        includes.extend(self.include_paths.iter().cloned());
+        import_graph(&discovered, &includes)?;
+        timings.validation = Some(started.elapsed());
+
+        // Held until this function returns, however it returns.
+        let _locks = if self.dry_run {
+            Vec::new()
+        } else {
+            let mut dirs: Vec<PathBuf> = self.staging_dir.iter().cloned().collect();
+            let out_dir = resolve_out_dir(&self.config)?;
+            if self.config.vendor {
+                lock::lock_dirs(&dirs, &[out_dir], self.lock_timeout)?
+            } else {
+                dirs.push(out_dir);
+                lock::lock_dirs(&dirs, &[], self.lock_timeout)?
+            }
+        };
+
+        // Mirror each source tree into its staging dir so package-relative imports still
+        // resolve. Compiling in place hands protoc the same relative layout, so the generated
+        // code is identical. Every root becomes an include path, which is what lets an import
+        // in one root name a file from another.
+        let mut protos = Vec::with_capacity(discovered.len());
+        let mut include_dirs = Vec::with_capacity(roots.len());
+        let mut unchanged = 0;
+        let started = Instant::now();
+        for (source_dir, dest_dir, discovered) in roots {
+            let Some(dest_dir) = dest_dir else {
+                protos.extend(discovered);
+                include_dirs.push(source_dir.to_path_buf());
+                continue;
+            };
+            if discovered.is_empty() {
+                continue;
+            }
+            if self.dry_run {
+                for (from, to) in
+                    plan_copies_stripped(source_dir, &dest_dir, &discovered, &self.strip_prefix)?
+                {
+                    println!(
+                        "cargo:warning=dry run: copy {} -> {}",
+                        from.display(),
+                        to.display()
+                    );
+                    protos.push(to);
+                }
+            } else {
+                if self.clean {
+                    let plan = plan_copies_stripped(
+                        source_dir,
+                        &dest_dir,
+                        &discovered,
+                        &self.strip_prefix,
+                    )?;
+                    let keep: Vec<PathBuf> = plan.into_iter().map(|(_, to)| to).collect();
+                    clean_stale(&dest_dir, &keep)?;
+                }
+                let (copied, skipped) =
+                    stage_protos(source_dir, &dest_dir, &discovered, &self.strip_prefix)?;
+                protos.extend(copied);
+                unchanged += skipped;
+            }
+            include_dirs.push(dest_dir);
+        }
+        // Only listed protos are staged, but they may import unlisted ones. The source dirs go
+        // after the staging dirs, so they only serve imports that weren't staged.
+        if self.file_list.is_some() && self.staging_dir.is_some() {
+            include_dirs.extend(self.source_dirs.iter().cloned());
+        }
+        include_dirs.extend(self.include_paths.iter().cloned());
+        // Compiling in place copies nothing, which is not the same as copying quickly.
+        if self.staging_dir.is_some() {
+            timings.copy = Some(started.elapsed());
+        }
+
+        if self.dry_run {
+            println!("cargo:warning=dry run: compile protos {protos:?}");
+            println!("cargo:warning=dry run: compile includes {include_dirs:?}");
+            return BuildManifest::new(&discovered, &[], None);
+        }
+        // prost-build reads PROTOC itself. It is only checked once protoc is about to run, so a
+        // cache hit or current vendored code builds without one.
+        let started = Instant::now();
+        println!("cargo:rerun-if-env-changed=PROTOC");
+        let config = self.merged_config(&discovered)?;
+        let output = compile_protos_with(&protos, &include_dirs, &config, self.min_protoc_version)?;
+        timings.compile = Some(started.elapsed());
+        let mut manifest = BuildManifest::new(
+            &protos,
+            &output.files,
+            self.config.descriptor_set.as_deref(),
+        )?;
+        manifest.modules = output.modules;
+        self.log_summary(discovered.len(), protos.len() - unchanged, unchanged)?;
+        Ok(manifest)
+    }
+
+    /// The configuration with the [`FileConfig`] of each of `protos` folded in. A per-file entry
+    /// replaces any global one for the same path.
+    fn merged_config(&self, protos: &[PathBuf]) -> Result<CompileConfig, BuildError> {
+        let mut config = self.config.clone();
+        for proto in protos {
+            let Some(file_config) = FileConfig::load_for(proto)? else {
+                continue;
+            };
+            log(
+                LogLevel::Debug,
+                format_args!("applying per-file options to {}", proto.display()),
+            );
+            let contents = parse::read_proto(proto)?;
+            let package = parse::package(&contents);
+            let types = parse::top_level_types(&contents);
+            let qualify = |path: &str| FileConfig::qualify(path, package.as_deref(), &types);
+
+            let mut type_attributes = Vec::new();
+            for entry in &file_config.type_attribute {
+                for path in qualify(&entry.path) {
+                    type_attributes.push((path, entry.attribute.clone()));
+                }
+            }
+            config
+                .type_attributes
+                .retain(|(path, _)| !type_attributes.iter().any(|(own, _)| own == path));
+            config.type_attributes.extend(type_attributes);
+            for entry in &file_config.boxed_field {
+                for path in qualify(&entry.path) {
+                    if !config.boxed.contains(&path) {
+                        config.boxed.push(path);
+                    }
+                }
+            }
+            for entry in file_config.extern_path {
+                config
+                    .extern_paths
+                    .retain(|(proto_path, _)| *proto_path != entry.proto_path);
+                config
+                    .extern_paths
+                    .push((entry.proto_path, entry.rust_path));
+            }
+        }
+        Ok(config)
+    }
+
+    /// One info line on what the build did, for telling at a glance why a type is missing.
+    fn log_summary(
+        &self,
+        discovered: usize,
+        copied: usize,
+        unchanged: usize,
+    ) -> Result<(), BuildError> {
+        let staging = match self.staging_dir {
+            Some(_) => format!("{copied} copied, {unchanged} unchanged"),
+            None => "compiled in place".to_string(),
+        };
+        let stubs = match (self.config.build_server, self.config.build_client) {
+            _ if self.config.messages_only => "messages only",
+            (true, true) => "server and client stubs",
+            (true, false) => "server stubs",
+            (false, true) => "client stubs",
+            (false, false) => "messages only",
+        };
+        let descriptor_set = match &self.config.descriptor_set {
+            Some(path) => format!("descriptor set `{}`", path.display()),
+            None => "no descriptor set".to_string(),
+        };
+        log(
+            LogLevel::Info,
+            format_args!(
+                "{discovered} proto(s) ({staging}), {stubs} in `{}`, {descriptor_set}",
+                resolve_out_dir(&self.config)?.display()
+            ),
+        );
+        Ok(())
+    }
+}
+
+/// The protos named in the file list `list`, resolved against `roots` and grouped by the first
+/// root holding each, canonicalized and sorted the way [`discover_protos`] sorts them.
+fn listed_protos(list: &Path, roots: &[PathBuf]) -> Result<Vec<Vec<PathBuf>>, BuildError> {
+    println!("cargo:rerun-if-changed={}", list.display());
+    let contents = match fs::read_to_string(list) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(BuildError::Config(format!(
+                "proto file list `{}` does not exist",
+                list.display()
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut listed = vec![Vec::new(); roots.len()];
+    for (number, line) in contents.lines().enumerate() {
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        let where_listed = format!("line {} of `{}`", number + 1, list.display());
+        // Entries stay inside their root so they can be staged relative to it.
+        let relative = Path::new(entry)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !relative {
+            return Err(BuildError::Config(format!(
+                "`{entry}` ({where_listed}) must be a path relative to a source dir"
+            )));
+        }
+        let Some(index) = roots.iter().position(|root| root.join(entry).is_file()) else {
+            return Err(BuildError::Config(format!(
+                "`{entry}` ({where_listed}) was not found under {}",
+                display_dirs(roots)
+            )));
+        };
+        listed[index].push(fs::canonicalize(roots[index].join(entry))?);
+    }
+    for (root, protos) in roots.iter().zip(&mut listed) {
+        if protos.is_empty() {
+            continue;
+        }
+        let root = fs::canonicalize(root)?;
+        protos.sort_by(|a, b| {
+            let a = a.strip_prefix(&root).unwrap_or(a);
+            let b = b.strip_prefix(&root).unwrap_or(b);
+            a.cmp(b)
+        });
+        protos.dedup();
+    }
+    Ok(listed)
+}
+
+/// Renders directories for messages as `` `a`, `b` ``.
+fn display_dirs(dirs: &[PathBuf]) -> String {
+    let dirs: Vec<String> = dirs
+        .iter()
+        .map(|dir| format!("`{}`", dir.display()))
+        .collect();
+    dirs.join(", ")
+}
+
+/// Wall-clock time spent in each phase of a run; `None` for a phase that never ran.
+#[derive(Debug, Default)]
+struct Timings {
+    discovery: Option<Duration>,
+    validation: Option<Duration>,
+    copy: Option<Duration>,
+    compile: Option<Duration>,
+}
+
+impl Timings {
+    fn print(&self) {
+        let phases = [
+            ("discovery", self.discovery),
+            ("validation", self.validation),
+            ("copy", self.copy),
+            ("compile", self.compile),
+        ];
+        for (phase, elapsed) in phases {
+            match elapsed {
+                Some(elapsed) => println!(
+                    "cargo:warning=timing: {phase} took {}ms",
+                    elapsed.as_millis()
+                ),
+                None => println!("cargo:warning=timing: {phase} skipped"),
+            }
+        }
+    }
+}
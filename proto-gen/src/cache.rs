@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::imports::ImportGraph;
+use crate::{parse, BuildError, CompileConfig, CompileOutput};
+
+/// Written into the out dir after each successful compile: the overall digest on the first
+/// line, then a `config <digest>` line, one `file <digest> <path>` line per input, one
+/// `module <name>` line per top-level module and one `output <path>` line per generated file.
+pub(crate) const CACHE_FILE: &str = ".proto-gen-cache";
+
+/// Hashes of everything that influences the generated code.
+pub(crate) struct Fingerprint {
+    /// This crate's version, the effective configuration and the include paths.
+    pub(crate) config: String,
+    /// The contents of each input file and of every proto it imports from the include paths,
+    /// directly or not. Well-known types bundled with protoc resolve to no file and are left
+    /// out; they can only change along with protoc.
+    pub(crate) files: Vec<(PathBuf, String)>,
+}
+
+impl Fingerprint {
+    pub(crate) fn new(
+        files: &[PathBuf],
+        includes: &[PathBuf],
+        config: &CompileConfig,
+    ) -> Result<Self, BuildError> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(format!("{config:?}\n{includes:?}\n"));
+        let config = format!("{:x}", hasher.finalize());
+
+        let graph = ImportGraph::build(files, includes)?;
+        let files = graph
+            .files()
+            .iter()
+            .map(|file| {
+                Ok((
+                    file.clone(),
+                    format!("{:x}", Sha256::digest(parse::read_proto(file)?)),
+                ))
+            })
+            .collect::<Result<_, BuildError>>()?;
+        Ok(Fingerprint { config, files })
+    }
+
+    /// A single digest over the configuration and every file's path and contents.
+    pub(crate) fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.config);
+        for (file, digest) in &self.files {
+            hasher.update(format!("\n{}\n{digest}", file.display()));
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// What the previous compile into an out dir recorded.
+pub(crate) struct Cached {
+    pub(crate) digest: String,
+    pub(crate) config: String,
+    pub(crate) files: HashMap<PathBuf, String>,
+    pub(crate) output: CompileOutput,
+}
+
+impl Cached {
+    /// Whether every generated file the previous compile recorded is still there.
+    pub(crate) fn outputs_exist(&self) -> bool {
+        self.output.files.iter().all(|file| file.is_file())
+    }
+}
+
+/// Reads the cache in `out_dir`, if there is one.
+pub(crate) fn load(out_dir: &Path) -> Result<Option<Cached>, BuildError> {
+    let cache = match fs::read_to_string(out_dir.join(CACHE_FILE)) {
+        Ok(cache) => cache,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut lines = cache.lines();
+    let mut cached = Cached {
+        digest: lines.next().unwrap_or_default().to_string(),
+        config: String::new(),
+        files: HashMap::new(),
+        output: CompileOutput::default(),
+    };
+    for line in lines {
+        if let Some(config) = line.strip_prefix("config ") {
+            cached.config = config.to_string();
+        } else if let Some((digest, file)) = line
+            .strip_prefix("file ")
+            .and_then(|rest| rest.split_once(' '))
+        {
+            cached.files.insert(PathBuf::from(file), digest.to_string());
+        } else if let Some(module) = line.strip_prefix("module ") {
+            cached.output.modules.push(module.to_string());
+        } else if let Some(file) = line.strip_prefix("output ") {
+            cached.output.files.push(PathBuf::from(file));
+        }
+    }
+    Ok(Some(cached))
+}
+
+/// Records `fingerprint` together with what the compile produced.
+pub(crate) fn store(
+    out_dir: &Path,
+    fingerprint: &Fingerprint,
+    output: &CompileOutput,
+) -> Result<(), BuildError> {
+    let mut cache = format!("{}\nconfig {}\n", fingerprint.digest(), fingerprint.config);
+    for (file, digest) in &fingerprint.files {
+        cache.push_str(&format!("file {digest} {}\n", file.display()));
+    }
+    for module in &output.modules {
+        cache.push_str(&format!("module {module}\n"));
+    }
+    for file in &output.files {
+        cache.push_str(&format!("output {}\n", file.display()));
+    }
+    fs::write(out_dir.join(CACHE_FILE), cache)?;
+    Ok(())
+}
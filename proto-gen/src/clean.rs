@@ -0,0 +1,122 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{log, BuildError, LogLevel};
+
+/// Lists, one per line, the files under the destination directory that [`crate::copy_protos`]
+/// put there. Only files named here are ever deleted by [`clean_stale`].
+pub const MANAGED_MANIFEST: &str = ".proto-gen-managed";
+
+/// Adds `copied` to the managed set for `dest_dir`. Entries from earlier builds are kept until
+/// [`clean_stale`] has removed their files, so a proto deleted while cleaning is off can still be
+/// cleaned up later.
+pub(crate) fn record_managed(dest_dir: &Path, copied: &[PathBuf]) -> Result<(), BuildError> {
+    let mut entries = read_managed(dest_dir)?;
+    entries.extend(
+        copied
+            .iter()
+            .filter_map(|path| path.strip_prefix(dest_dir).ok())
+            .map(|relative| relative.to_string_lossy().replace('\\', "/")),
+    );
+    write_managed(dest_dir, &entries)
+}
+
+/// The entries of the manifest in `dest_dir`, or none if there is no manifest yet.
+fn read_managed(dest_dir: &Path) -> Result<BTreeSet<String>, BuildError> {
+    match fs::read_to_string(dest_dir.join(MANAGED_MANIFEST)) {
+        Ok(manifest) => Ok(manifest.lines().map(str::to_string).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes `entries` as the manifest of `dest_dir`. The manifest is only rewritten when its
+/// contents change so the watched directory does not look modified on every build.
+fn write_managed(dest_dir: &Path, entries: &BTreeSet<String>) -> Result<(), BuildError> {
+    let contents: String = entries.iter().map(|entry| format!("{entry}\n")).collect();
+    let path = dest_dir.join(MANAGED_MANIFEST);
+    if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Deletes previously copied `.proto` files under `dest_dir` that are not in `keep`, i.e. whose
+/// source has since been renamed or removed. Files the manifest doesn't list are left alone.
+///
+/// Returns the removed paths.
+pub fn clean_stale(dest_dir: &Path, keep: &[PathBuf]) -> Result<Vec<PathBuf>, BuildError> {
+    let mut entries = read_managed(dest_dir)?;
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+    let keep: HashSet<&Path> = keep.iter().map(PathBuf::as_path).collect();
+
+    let mut removed = Vec::new();
+    // An entry leaves the manifest only once its file is gone, so a failed removal is retried by
+    // the next clean.
+    for entry in entries
+        .clone()
+        .iter()
+        .filter(|entry| entry.ends_with(".proto"))
+    {
+        let path = dest_dir.join(entry);
+        if keep.contains(path.as_path()) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                log(
+                    LogLevel::Debug,
+                    format_args!("removed stale {}", path.display()),
+                );
+                removed.push(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                write_managed(dest_dir, &entries)?;
+                return Err(err.into());
+            }
+        }
+        entries.remove(entry);
+    }
+    write_managed(dest_dir, &entries)?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copy_protos;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn orphan_copied_before_a_non_clean_build_is_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dest) = (tmp.path().join("src"), tmp.path().join("dest"));
+        let (cur, old) = (src.join("a/cur.proto"), src.join("a/old.proto"));
+        write(&cur, "syntax = \"proto3\";\n");
+        write(&old, "syntax = \"proto3\";\n");
+        copy_protos(&src, &dest, &[cur.clone(), old.clone()]).unwrap();
+
+        // Deleted while cleaning is off: the next copy must not forget it was managed.
+        fs::remove_file(&old).unwrap();
+        copy_protos(&src, &dest, std::slice::from_ref(&cur)).unwrap();
+        write(&dest.join("a/mine.proto"), "user file");
+
+        let removed = clean_stale(&dest, &[dest.join("a/cur.proto")]).unwrap();
+        assert_eq!(removed, vec![dest.join("a/old.proto")]);
+        assert!(dest.join("a/cur.proto").exists());
+        assert!(dest.join("a/mine.proto").exists());
+        assert_eq!(
+            fs::read_to_string(dest.join(MANAGED_MANIFEST)).unwrap(),
+            "a/cur.proto\n"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::BuildError;
+
+/// Default name of the optional per-crate codegen config.
+pub const CONFIG_FILE: &str = "proto-gen.toml";
+
+/// Appended to a proto's file name to find its optional [`FileConfig`]: `foo.proto.gen.toml`.
+pub const FILE_CONFIG_SUFFIX: &str = ".gen.toml";
+
+/// Contents of a `proto-gen.toml` file.
+///
+/// ```toml
+/// [[type_attribute]]
+/// path = ".billing.InvoiceKey"
+/// attribute = "#[derive(Eq, Hash)]"
+///
+/// [[field_attribute]]
+/// path = ".billing.Invoice.total"
+/// attribute = "#[serde(rename = \"amount\")]"
+///
+/// [[module_map]]
+/// package = "com.acme.billing.v1"
+/// module = "billing"
+///
+/// [[boxed_field]]
+/// path = ".tree.Node.children"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenConfig {
+    #[serde(default)]
+    pub type_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub field_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub module_map: Vec<ModuleMapping>,
+    #[serde(default)]
+    pub boxed_field: Vec<BoxedField>,
+}
+
+/// An attribute to attach to the generated item at a fully-qualified proto `path`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attribute {
+    pub path: String,
+    pub attribute: String,
+}
+
+/// Re-exports the generated code for `package` under the Rust module path `module`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleMapping {
+    pub package: String,
+    pub module: String,
+}
+
+/// A message field to store behind a `Box`, typically to break up a recursive type.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoxedField {
+    pub path: String,
+}
+
+/// Contents of a `foo.proto.gen.toml` file next to `foo.proto`, for options that only concern
+/// that file.
+///
+/// Paths without a leading `.` are relative to the file's package, and `.` on its own stands for
+/// every type the file declares, so the options can't leak onto other files' types:
+///
+/// ```toml
+/// [[type_attribute]]
+/// path = "."
+/// attribute = "#[derive(serde::Serialize)]"
+///
+/// [[boxed_field]]
+/// path = "Node.children"
+///
+/// [[extern_path]]
+/// proto_path = ".google.type"
+/// rust_path = "::google_types"
+/// ```
+///
+/// Extern paths map whole proto packages, so unlike the rest they apply to the entire build.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    #[serde(default)]
+    pub type_attribute: Vec<Attribute>,
+    #[serde(default)]
+    pub boxed_field: Vec<BoxedField>,
+    #[serde(default)]
+    pub extern_path: Vec<ExternPath>,
+}
+
+/// Types under `proto_path` come from the Rust path `rust_path` instead of being generated.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternPath {
+    pub proto_path: String,
+    pub rust_path: String,
+}
+
+impl FileConfig {
+    /// Loads the sibling config of `proto`, if it has one.
+    pub fn load_for(proto: &Path) -> Result<Option<Self>, BuildError> {
+        let mut path = proto.as_os_str().to_owned();
+        path.push(FILE_CONFIG_SUFFIX);
+        let path = Path::new(&path);
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        toml::from_str(&contents).map(Some).map_err(|err| {
+            BuildError::Config(format!("failed to parse `{}`: {err}", path.display()))
+        })
+    }
+
+    /// Fully qualifies a relative `path` against `package`, expanding `.` to each of `types`,
+    /// the top-level types of the file.
+    pub(crate) fn qualify(path: &str, package: Option<&str>, types: &[String]) -> Vec<String> {
+        let prefix = package.map_or_else(String::new, |package| format!(".{package}"));
+        match path {
+            "." => types
+                .iter()
+                .map(|name| format!("{prefix}.{name}"))
+                .collect(),
+            _ if path.starts_with('.') => vec![path.to_string()],
+            _ => vec![format!("{prefix}.{path}")],
+        }
+    }
+}
+
+impl GenConfig {
+    /// Loads `path`, treating a missing file as an empty config. Malformed TOML is an error.
+    pub fn load(path: &Path) -> Result<Self, BuildError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(GenConfig::default()),
+            Err(err) => return Err(err.into()),
+        };
+        toml::from_str(&contents).map_err(|err| {
+            BuildError::Config(format!("failed to parse `{}`: {err}", path.display()))
+        })
+    }
+}
@@ -0,0 +1,81 @@
+//! Keeps comments copied from the protos from breaking the generated docs.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{log, BuildError, LogLevel};
+
+/// Rewrites the `.rs` files in `files` so every code fence in a `///` block is closed before the
+/// block ends, and fences rustdoc would run as a doctest are marked `text`. Otherwise a comment
+/// with a stray fence turns the rest of the item's docs into Rust that `cargo test` compiles.
+/// prost's markdown cleanup already closes fences, but keeps ```` ```rust ```` examples as
+/// `compile_fail` tests, which fail as soon as the example does compile. Files with nothing to
+/// change are left alone, mtime included.
+pub(crate) fn balance_code_fences(files: &[PathBuf]) -> Result<(), BuildError> {
+    for file in files
+        .iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+    {
+        let original = fs::read_to_string(file)?;
+        let balanced = balance(&original);
+        if balanced != original {
+            log(
+                LogLevel::Debug,
+                format_args!("balanced code fences in {}", file.display()),
+            );
+            fs::write(file, balanced)?;
+        }
+    }
+    Ok(())
+}
+
+fn balance(contents: &str) -> String {
+    let mut balanced = String::with_capacity(contents.len());
+    // The comment prefix (`    ///`) and fence marker of the block still open.
+    let mut open: Option<(&str, &str)> = None;
+    for line in contents.split_inclusive('\n') {
+        let Some(start) = line.find("///").filter(|&at| line[..at].trim().is_empty()) else {
+            if let Some((prefix, marker)) = open.take() {
+                balanced.push_str(&format!("{prefix} {marker}\n"));
+            }
+            balanced.push_str(line);
+            continue;
+        };
+        let prefix = &line[..start + 3];
+        let text = line[start + 3..].trim_start();
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| text.starts_with(marker));
+        match (marker, open) {
+            (Some(marker), None) => {
+                let info = text.trim_start_matches(&marker[..1]).trim();
+                if is_doctest(info) {
+                    balanced.push_str(&format!("{prefix} {marker}text\n"));
+                } else {
+                    balanced.push_str(line);
+                }
+                open = Some((prefix, marker));
+            }
+            (Some(marker), Some((_, opened))) if marker == opened => {
+                balanced.push_str(line);
+                open = None;
+            }
+            _ => balanced.push_str(line),
+        }
+    }
+    if let Some((prefix, marker)) = open {
+        balanced.push_str(&format!("{prefix} {marker}\n"));
+    }
+    balanced
+}
+
+/// Whether rustdoc treats a fence with this info string as a Rust doctest: no language, or only
+/// `rust` and rustdoc's own attributes.
+fn is_doctest(info: &str) -> bool {
+    info.split(',').map(str::trim).all(|token| {
+        matches!(
+            token,
+            "" | "rust" | "compile_fail" | "should_panic" | "no_run" | "standalone_crate"
+        ) || token.starts_with("edition")
+    })
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Everything that can go wrong while preparing and compiling the proto set.
+#[derive(Debug)]
+pub enum BuildError {
+    /// An explicitly configured source directory does not exist.
+    SourceMissing(PathBuf),
+    /// Reading, copying or creating files failed.
+    Io(io::Error),
+    /// The code generator rejected the proto set.
+    Compile(String),
+    /// No `.proto` files were found under the given directories and empty sets are not allowed.
+    EmptyProtoSet(Vec<PathBuf>),
+    /// One or more files failed the pre-compile validation pass.
+    Invalid(Vec<ProtoError>),
+    /// A setting could not be understood.
+    Config(String),
+    /// A `.proto` file is not valid UTF-8 text.
+    NotUtf8(PathBuf),
+    /// The protos import each other in a loop; holds the chain of files, ending where it began.
+    CircularImport(Vec<String>),
+    /// No protoc could be run from this path: `PROTOC` names a missing file, or there is no
+    /// `protoc` on the `PATH`.
+    ProtocUnavailable(PathBuf),
+    /// Another build held the lock file at this path for longer than the timeout.
+    LockTimeout(PathBuf, Duration),
+    /// The vendored code in this directory no longer matches what the protos generate; holds
+    /// one line per differing file.
+    VendorOutdated(PathBuf, Vec<String>),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::SourceMissing(dir) => {
+                write!(
+                    f,
+                    "proto source directory `{}` does not exist",
+                    dir.display()
+                )
+            }
+            BuildError::Io(err) => write!(f, "I/O error: {err}"),
+            BuildError::Compile(msg) => write!(f, "failed to compile protos: {msg}"),
+            BuildError::EmptyProtoSet(dirs) => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| format!("`{}`", dir.display()))
+                    .collect();
+                write!(f, "no .proto files found under {}", dirs.join(", "))
+            }
+            BuildError::Invalid(errors) => {
+                write!(f, "{} problem(s) found in proto files", errors.len())
+            }
+            BuildError::Config(msg) => f.write_str(msg),
+            BuildError::NotUtf8(file) => {
+                write!(f, "`{}` is not valid UTF-8 text", file.display())
+            }
+            BuildError::CircularImport(chain) => {
+                write!(f, "circular import: {}", chain.join(" -> "))
+            }
+            BuildError::ProtocUnavailable(protoc) => write!(
+                f,
+                "protoc is not available (looked for `{}`); install it with your package manager \
+                 (`apt install protobuf-compiler`, `brew install protobuf`) or from \
+                 https://github.com/protocolbuffers/protobuf/releases, or set PROTOC to the path \
+                 of an existing protoc binary",
+                protoc.display()
+            ),
+            BuildError::LockTimeout(path, timeout) => write!(
+                f,
+                "gave up after {}s waiting for another build to release `{}`",
+                timeout.as_secs(),
+                path.display()
+            ),
+            BuildError::VendorOutdated(dir, differences) => write!(
+                f,
+                "vendored code in `{}` is out of date ({} file(s) differ); regenerate it",
+                dir.display(),
+                differences.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> Self {
+        BuildError::Io(err)
+    }
+}
+
+/// A problem found in a proto file before it is handed to the code generator.
+#[derive(Debug)]
+pub struct ProtoError {
+    pub file: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.reason)
+    }
+}
@@ -0,0 +1,59 @@
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorSet};
+
+use crate::BuildError;
+
+/// Returns the entries of `paths` that don't select any field in the encoded `descriptor_set`,
+/// using the same rules prost applies to `boxed`: a path starting with `.` matches a fully
+/// qualified field or everything beneath a package or message prefix, anything else matches a
+/// trailing run of components such as `Node.children`.
+pub(crate) fn unmatched_field_paths(
+    descriptor_set: &[u8],
+    paths: &[String],
+) -> Result<Vec<String>, BuildError> {
+    let set = FileDescriptorSet::decode(descriptor_set)
+        .map_err(|err| BuildError::Config(format!("failed to decode descriptor set: {err}")))?;
+    let mut fields = Vec::new();
+    for file in &set.file {
+        let package = match file.package() {
+            "" => String::new(),
+            package => format!(".{package}"),
+        };
+        for message in &file.message_type {
+            collect_fields(&package, message, &mut fields);
+        }
+    }
+
+    Ok(paths
+        .iter()
+        .filter(|path| !fields.iter().any(|field| selects(path, field)))
+        .cloned()
+        .collect())
+}
+
+fn collect_fields(scope: &str, message: &DescriptorProto, fields: &mut Vec<String>) {
+    let scope = format!("{scope}.{}", message.name());
+    for field in &message.field {
+        fields.push(format!("{scope}.{}", field.name()));
+    }
+    for nested in &message.nested_type {
+        collect_fields(&scope, nested, fields);
+    }
+}
+
+fn selects(path: &str, field: &str) -> bool {
+    if path == "." {
+        return true;
+    }
+    if path.starts_with('.') {
+        field == path
+            || field
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('.'))
+    } else {
+        field == path
+            || field
+                .strip_suffix(path)
+                .is_some_and(|rest| rest.ends_with('.'))
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+
+use crate::BuildError;
+
+/// Include/exclude globs applied to discovered protos, matched against each file's path
+/// relative to its source root (so `api/**` or `*_test.proto`).
+#[derive(Debug, Clone, Default)]
+pub struct ProtoFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl ProtoFilter {
+    /// Compiles the glob lists; an empty `include` list means "everything".
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, BuildError> {
+        Ok(ProtoFilter {
+            include: compile_patterns(include)?,
+            exclude: compile_patterns(exclude)?,
+        })
+    }
+
+    /// Keeps the files under `root` that match an include glob (or all, without includes)
+    /// and no exclude glob. Excludes win when both match.
+    ///
+    /// Also returns the include globs that matched none of `files`, excluded or not, since a
+    /// typo in one otherwise just silently selects less.
+    pub fn apply(&self, root: &Path, files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<String>) {
+        // Discovered paths are canonical, so compare against the canonical root as well.
+        let canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut matched = vec![false; self.include.len()];
+        let kept = files
+            .into_iter()
+            .filter(|path| {
+                let relative = path
+                    .strip_prefix(&canonical_root)
+                    .or_else(|_| path.strip_prefix(root))
+                    .unwrap_or(path);
+                let mut included = self.include.is_empty();
+                for (glob, matched) in self.include.iter().zip(&mut matched) {
+                    if glob.matches_path(relative) {
+                        *matched = true;
+                        included = true;
+                    }
+                }
+                included && !self.exclude.iter().any(|glob| glob.matches_path(relative))
+            })
+            .collect();
+        let unmatched = self
+            .include
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(glob, _)| glob.as_str().to_string())
+            .collect();
+        (kept, unmatched)
+    }
+}
+
+fn compile_patterns(globs: &[String]) -> Result<Vec<Pattern>, BuildError> {
+    globs
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob)
+                .map_err(|err| BuildError::Config(format!("invalid glob `{glob}`: {err}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_glob_matching_nothing_is_reported() {
+        let root = Path::new("/protos");
+        let files = vec![
+            root.join("api/v1/user.proto"),
+            root.join("internal/db.proto"),
+        ];
+        let filter = ProtoFilter::new(
+            &["api/**".to_string(), "tpyo/**".to_string()],
+            &["api/v1/*".to_string()],
+        )
+        .unwrap();
+
+        // `api/**` matched even though everything it selected was excluded.
+        let (kept, unmatched) = filter.apply(root, files);
+        assert!(kept.is_empty());
+        assert_eq!(unmatched, ["tpyo/**"]);
+    }
+}
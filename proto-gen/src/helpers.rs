@@ -0,0 +1,108 @@
+//! Optional `connect` helpers for the generated gRPC clients.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use heck::ToUpperCamelCase;
+
+use crate::modules::module_ident;
+use crate::{parse, BuildError};
+
+/// The helper file written for `package` when [`CompileConfig::client_helpers`] is on and the
+/// package declares services. Package names can't contain `-`, so prost never writes a
+/// `foo.client-helpers.rs` of its own; [`module_file_name`] relies on the same rule.
+///
+/// [`CompileConfig::client_helpers`]: crate::CompileConfig::client_helpers
+/// [`module_file_name`]: crate::module_file_name
+pub fn client_helpers_file_name(package: Option<&str>) -> String {
+    format!("{}.client-helpers.rs", package.unwrap_or("_"))
+}
+
+/// The services each package declares, leaving out packages without any.
+pub(crate) fn services_by_package(
+    files: &[PathBuf],
+    packages: &[Option<String>],
+) -> Result<BTreeMap<Option<String>, Vec<String>>, BuildError> {
+    let mut services: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for (file, package) in files.iter().zip(packages) {
+        let declared = parse::services(&parse::read_proto(file)?);
+        if !declared.is_empty() {
+            services
+                .entry(package.clone())
+                .or_default()
+                .extend(declared);
+        }
+    }
+    Ok(services)
+}
+
+/// Writes one [`client_helpers_file_name`] file per package in `services`. Each service gets a
+/// module named after it holding `connect`, which is meant to sit next to the generated
+/// `<service>_client` module.
+pub(crate) fn write_client_helpers(
+    out_dir: &Path,
+    services: &BTreeMap<Option<String>, Vec<String>>,
+) -> Result<(), BuildError> {
+    for (package, names) in services {
+        let mut helpers = String::from("// @generated by proto-gen. Do not edit.\n");
+        for name in names {
+            let (module, client) = client_names(name);
+            let _ = write!(
+                helpers,
+                "/// Helpers for the `{name}` service.
+pub mod {helper} {{
+    /// Connects the generated client to `endpoint`, such as `http://[::1]:50051`.
+    pub async fn connect(
+        endpoint: &str,
+    ) -> ::core::result::Result<
+        super::{module}::{client}<::tonic::transport::Channel>,
+        ::tonic::transport::Error,
+    > {{
+        super::{module}::{client}::connect(endpoint.to_string()).await
+    }}
+}}
+",
+                helper = module_ident(&snake_case(&service_ident(name))),
+            );
+        }
+        fs::write(
+            out_dir.join(client_helpers_file_name(package.as_deref())),
+            helpers,
+        )?;
+    }
+    Ok(())
+}
+
+/// The client module and type tonic generates for the proto service `name`, e.g.
+/// `greeter_client` and `GreeterClient` for `Greeter`.
+fn client_names(name: &str) -> (String, String) {
+    let ident = service_ident(name);
+    (
+        format!("{}_client", snake_case(&ident)),
+        format!("{ident}Client"),
+    )
+}
+
+/// The service's Rust name as prost hands it to tonic: upper camel case, with `Self` escaped.
+/// No other upper camel case word is a keyword.
+fn service_ident(name: &str) -> String {
+    match name.to_upper_camel_case() {
+        ident if ident == "Self" => "Self_".to_string(),
+        ident => ident,
+    }
+}
+
+/// tonic's own snake casing: lower-case everything and put `_` before each upper-case letter.
+fn snake_case(ident: &str) -> String {
+    let mut snake = String::with_capacity(ident.len() + 4);
+    let mut chars = ident.chars().peekable();
+    while let Some(c) = chars.next() {
+        snake.push(c.to_ascii_lowercase());
+        if chars.peek().is_some_and(|next| next.is_uppercase()) {
+            snake.push('_');
+        }
+    }
+    snake
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{parse, BuildError};
+
+/// Which protos import which, keyed by canonical path.
+///
+/// Imports are resolved the way protoc does it: always relative to an include path, trying them
+/// in order, never relative to the importing file. Imports that resolve nowhere (such as the
+/// well-known types bundled with protoc) are left out and reported by protoc if they matter.
+pub(crate) struct ImportGraph {
+    /// Every file reached from the roots, in discovery order.
+    files: Vec<PathBuf>,
+    /// How each file is named in messages: its path under the include directory holding it.
+    names: HashMap<PathBuf, String>,
+    imports: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl ImportGraph {
+    /// Parses `files` and, transitively, every proto they import from `includes`.
+    pub(crate) fn build(files: &[PathBuf], includes: &[PathBuf]) -> Result<Self, BuildError> {
+        let includes: Vec<PathBuf> = includes
+            .iter()
+            .filter_map(|dir| fs::canonicalize(dir).ok())
+            .collect();
+        let mut graph = ImportGraph {
+            files: Vec::new(),
+            names: HashMap::new(),
+            imports: HashMap::new(),
+        };
+        let mut pending = files
+            .iter()
+            .map(fs::canonicalize)
+            .collect::<Result<Vec<_>, _>>()?;
+        pending.reverse();
+
+        while let Some(file) = pending.pop() {
+            if graph.imports.contains_key(&file) {
+                continue;
+            }
+            let mut resolved = Vec::new();
+            for import in parse::imports(&parse::read_proto(&file)?) {
+                let Some(target) = includes
+                    .iter()
+                    .map(|dir| dir.join(&import))
+                    .find(|path| path.is_file())
+                else {
+                    continue;
+                };
+                let target = fs::canonicalize(target)?;
+                graph.names.entry(target.clone()).or_insert(import);
+                resolved.push(target);
+            }
+            let name = includes
+                .iter()
+                .find_map(|dir| file.strip_prefix(dir).ok())
+                .map_or_else(|| file.display().to_string(), display_relative);
+            graph.names.entry(file.clone()).or_insert(name);
+            pending.extend(resolved.iter().rev().cloned());
+            graph.imports.insert(file.clone(), resolved);
+            graph.files.push(file);
+        }
+        Ok(graph)
+    }
+
+    /// Every file reached from the roots, the roots included, in discovery order.
+    pub(crate) fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The first import cycle found, as the chain of file names from the first file on the cycle
+    /// back to itself.
+    pub(crate) fn find_cycle(&self) -> Option<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            graph: &'a ImportGraph,
+            file: &'a Path,
+            states: &mut HashMap<&'a Path, State>,
+            stack: &mut Vec<&'a Path>,
+        ) -> Option<Vec<String>> {
+            match states.get(file) {
+                Some(State::Done) => return None,
+                Some(State::Visiting) => {
+                    let start = stack.iter().position(|entry| *entry == file)?;
+                    let mut chain: Vec<String> = stack[start..]
+                        .iter()
+                        .map(|entry| graph.name(entry))
+                        .collect();
+                    chain.push(graph.name(file));
+                    return Some(chain);
+                }
+                None => {}
+            }
+            states.insert(file, State::Visiting);
+            stack.push(file);
+            for import in graph.imports.get(file).into_iter().flatten() {
+                if let Some(chain) = visit(graph, import, states, stack) {
+                    return Some(chain);
+                }
+            }
+            stack.pop();
+            states.insert(file, State::Done);
+            None
+        }
+
+        let mut states = HashMap::new();
+        self.files
+            .iter()
+            .find_map(|file| visit(self, file, &mut states, &mut Vec::new()))
+    }
+
+    /// `changed` plus every file that imports one of them, directly or through other files.
+    pub(crate) fn dependents(&self, changed: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+        let mut importers: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for (file, imports) in &self.imports {
+            for import in imports {
+                importers.entry(import).or_default().push(file);
+            }
+        }
+
+        let mut affected = changed.clone();
+        let mut pending: Vec<&Path> = changed.iter().map(PathBuf::as_path).collect();
+        while let Some(file) = pending.pop() {
+            for importer in importers.get(file).into_iter().flatten() {
+                if affected.insert(importer.to_path_buf()) {
+                    pending.push(importer);
+                }
+            }
+        }
+        affected
+    }
+
+    fn name(&self, file: &Path) -> String {
+        self.names
+            .get(file)
+            .cloned()
+            .unwrap_or_else(|| file.display().to_string())
+    }
+}
+
+fn display_relative(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Parses the imports of `files`, resolving them against `includes`, and fails with
+/// [`BuildError::CircularImport`] if any chain of imports leads back to where it started.
+///
+/// This is much quicker than waiting for protoc, whose report of a cycle is hard to read.
+pub fn import_graph(files: &[PathBuf], includes: &[PathBuf]) -> Result<(), BuildError> {
+    match ImportGraph::build(files, includes)?.find_cycle() {
+        Some(chain) => Err(BuildError::CircularImport(chain)),
+        None => Ok(()),
+    }
+}
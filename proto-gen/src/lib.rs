@@ -0,0 +1,1016 @@
+//! Build-script helpers for turning a tree of `.proto` files into Rust code.
+//!
+//! A typical `build.rs` discovers the protos under a source root, mirrors them into a staging
+//! directory with [`copy_protos`], and hands the result to [`compile`]. [`ProtoGen`] chains
+//! those steps (plus validation and the protoc check) behind a single builder.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+mod builder;
+mod cache;
+mod clean;
+mod config;
+mod docs;
+mod error;
+mod fields;
+mod filter;
+mod helpers;
+mod imports;
+mod lock;
+mod log;
+mod manifest;
+mod modules;
+mod parse;
+mod prefix;
+mod protoc;
+mod rustfmt;
+mod validate;
+mod vendor;
+mod wkt;
+
+pub use builder::ProtoGen;
+pub use clean::{clean_stale, MANAGED_MANIFEST};
+pub use config::{
+    Attribute, BoxedField, ExternPath, FileConfig, GenConfig, ModuleMapping, CONFIG_FILE,
+    FILE_CONFIG_SUFFIX,
+};
+pub use error::{BuildError, ProtoError};
+pub use filter::ProtoFilter;
+pub use helpers::client_helpers_file_name;
+pub use imports::import_graph;
+pub use lock::{DEFAULT_LOCK_TIMEOUT, LOCK_FILE};
+pub use log::{log, set_log_level, LogLevel};
+pub use manifest::{Artifact, BuildManifest};
+pub use modules::{
+    generated_file_name, module_file_name, write_module_index, write_module_map, DEFAULT_MODULE,
+    MODULE_INDEX, MODULE_MAP_INDEX,
+};
+pub use protoc::{check_protoc, protoc_version, ProtocVersion};
+pub use rustfmt::format_generated;
+pub use validate::validate_protos;
+pub use vendor::{strip_checksum, CHECKSUM_HEADER};
+pub use wkt::{WellKnownTypes, NATIVE_SHIM};
+
+/// Code generation options passed through to `tonic_prost_build`.
+#[derive(Debug, Clone)]
+pub struct CompileConfig {
+    /// Generate the gRPC server stubs.
+    pub build_server: bool,
+    /// Generate the gRPC client stubs.
+    pub build_client: bool,
+    /// Generate client stubs for gRPC-Web. Browser clients ride on a transport such as
+    /// `tonic-web-wasm-client` rather than tonic's native one, so the `connect` constructors
+    /// that need tonic's `transport` feature are left out. Server stubs are unaffected and can be
+    /// served to grpc-web clients through `tonic_web`.
+    pub grpc_web: bool,
+    /// Write a [`client_helpers_file_name`] file for each package with services, holding an
+    /// async `connect(endpoint)` per service that returns its generated client over a tonic
+    /// `Channel`. The module index includes them. Needs client stubs with tonic's transport, so
+    /// it does nothing without `build_client` or with `grpc_web`.
+    pub client_helpers: bool,
+    /// Generate only the message types, with plain `prost_build` and no tonic code generation at
+    /// all. Services in the protos are left out with a warning, and `build_server`,
+    /// `build_client`, `grpc_web` and `client_helpers` have no effect.
+    pub messages_only: bool,
+    /// Compatibility flag for protoc older than 3.15, which rejects `optional` on proto3 fields
+    /// unless passed `--experimental_allow_proto3_optional`. It changes no generated code: newer
+    /// releases accept the keyword anyway, and `optional int32 n = 1;` becomes `Option<i32>`
+    /// either way.
+    pub proto3_optional: bool,
+    /// Copy the leading comments of messages, enums, fields, services and methods into the
+    /// generated code as `///` doc comments. Code fences a comment leaves open are closed, and
+    /// untagged ones marked `text`, so the docs never turn into doctests that fail to compile.
+    pub doc_comments: bool,
+    /// `(path, attribute)` pairs applied with `type_attribute`.
+    pub type_attributes: Vec<(String, String)>,
+    /// `(path, attribute)` pairs applied with `field_attribute`.
+    pub field_attributes: Vec<(String, String)>,
+    /// Paths of fields stored behind a `Box`, applied with `boxed`. Paths that match no field
+    /// are reported with a warning.
+    pub boxed: Vec<String>,
+    /// `(proto_path, rust_path)` pairs whose types come from another crate instead of being
+    /// generated here.
+    pub extern_paths: Vec<(String, String)>,
+    /// Representation of the `google.protobuf` well-known types.
+    pub well_known_types: WellKnownTypes,
+    /// Where to write the encoded `FileDescriptorSet`, if anywhere.
+    pub descriptor_set: Option<PathBuf>,
+    /// `(package, module)` pairs re-exported from [`MODULE_MAP_INDEX`] under shorter module
+    /// paths such as `billing` or `api::billing`.
+    pub module_map: Vec<(String, String)>,
+    /// Directory receiving the generated Rust; Cargo's `OUT_DIR` when unset.
+    pub out_dir: Option<PathBuf>,
+    /// Skip protoc when the inputs and configuration hash to the same digest as the last
+    /// successful compile into `out_dir` and its output is still there. The inputs include the
+    /// protos the compiled files import from the include paths.
+    pub cache: bool,
+    /// Hand protoc only the protos whose contents changed since the last compile into
+    /// `out_dir`, the protos importing them and the rest of their packages.
+    pub incremental: bool,
+    /// `rustfmt` binary to run over the generated Rust, or `None` to leave it as prost wrote it.
+    pub rustfmt: Option<PathBuf>,
+    /// `out_dir` is a committed vendor directory: every generated `.rs` file starts with a
+    /// [`CHECKSUM_HEADER`], and protoc is not run at all while the headers match the inputs.
+    /// No cache or lock file is written there, so a build that finds it current leaves it
+    /// untouched.
+    pub vendor: bool,
+}
+
+impl Default for CompileConfig {
+    fn default() -> Self {
+        CompileConfig {
+            build_server: true,
+            build_client: true,
+            grpc_web: false,
+            client_helpers: false,
+            messages_only: false,
+            proto3_optional: false,
+            doc_comments: true,
+            type_attributes: Vec::new(),
+            field_attributes: Vec::new(),
+            boxed: Vec::new(),
+            extern_paths: Vec::new(),
+            well_known_types: WellKnownTypes::Default,
+            descriptor_set: None,
+            module_map: Vec::new(),
+            out_dir: None,
+            cache: true,
+            incremental: false,
+            rustfmt: None,
+            vendor: false,
+        }
+    }
+}
+
+/// Recursively collects every `.proto` file under `root`.
+///
+/// Paths are canonicalized and deduplicated, so a file reachable through several symlinked
+/// directories is only listed once, and symlink loops are not followed. The list is sorted by
+/// path relative to `root`, so it comes out the same whatever order the filesystem lists
+/// directories in. A missing or empty tree yields an empty list rather than an error.
+pub fn discover_protos(root: &Path) -> Result<Vec<PathBuf>, BuildError> {
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let root = fs::canonicalize(root)?;
+    let mut protos = Vec::new();
+    walk(&root, &mut HashSet::new(), &mut protos)?;
+
+    let mut seen = HashSet::new();
+    let mut unique = Vec::with_capacity(protos.len());
+    for path in protos {
+        let path = fs::canonicalize(path)?;
+        if seen.insert(path.clone()) {
+            unique.push(path);
+        }
+    }
+    // Files symlinked in from outside the root have no relative path and sort by their own.
+    unique.sort_by(|a, b| {
+        let a = a.strip_prefix(&root).unwrap_or(a);
+        let b = b.strip_prefix(&root).unwrap_or(b);
+        a.cmp(b)
+    });
+    for path in &unique {
+        log(
+            LogLevel::Debug,
+            format_args!("discovered {}", path.display()),
+        );
+    }
+    Ok(unique)
+}
+
+fn walk(
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    protos: &mut Vec<PathBuf>,
+) -> Result<(), BuildError> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, visited, protos)?;
+        } else if path.extension().is_some_and(|ext| ext == "proto") {
+            protos.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Copies each of `files` from `source_dir` into `dest_dir`, keeping its path relative to
+/// `source_dir` so that files sharing a name in different packages never collide.
+///
+/// Large trees are split across a few threads. Sibling files racing to create a shared parent
+/// directory is fine because `create_dir_all` treats an already-existing directory as success.
+/// The copied set is recorded in [`MANAGED_MANIFEST`] for [`clean_stale`].
+/// Returns the destination paths in the same order as `files`.
+pub fn copy_protos(
+    source_dir: &Path,
+    dest_dir: &Path,
+    files: &[PathBuf],
+) -> Result<Vec<PathBuf>, BuildError> {
+    copy_protos_stripped(source_dir, dest_dir, files, Path::new(""))
+}
+
+/// Like [`copy_protos`], but drops `strip_prefix` (such as `com/acme`) from the front of each
+/// relative path and rewrites imports under it to match, so the staged tree still compiles.
+/// Files outside the prefix and imports of the well-known types are left alone.
+pub fn copy_protos_stripped(
+    source_dir: &Path,
+    dest_dir: &Path,
+    files: &[PathBuf],
+    strip_prefix: &Path,
+) -> Result<Vec<PathBuf>, BuildError> {
+    stage_protos(source_dir, dest_dir, files, strip_prefix).map(|(copied, _)| copied)
+}
+
+/// [`copy_protos_stripped`], also counting the files that were already up to date.
+pub(crate) fn stage_protos(
+    source_dir: &Path,
+    dest_dir: &Path,
+    files: &[PathBuf],
+    strip_prefix: &Path,
+) -> Result<(Vec<PathBuf>, usize), BuildError> {
+    let plan = plan_copies_stripped(source_dir, dest_dir, files, strip_prefix)?;
+    let workers = thread::available_parallelism().map_or(1, usize::from);
+    let chunk_size = plan.len().div_ceil(workers).max(1);
+    let copied = thread::scope(|scope| {
+        let handles: Vec<_> = plan
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(from, to)| copy_proto(from, to, strip_prefix))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+            })
+            .collect();
+
+        let mut copied = Vec::with_capacity(plan.len());
+        let mut unchanged = 0;
+        for handle in handles {
+            for (path, written) in handle.join().expect("proto copy thread panicked")? {
+                copied.push(path);
+                unchanged += usize::from(!written);
+            }
+        }
+        Ok::<_, BuildError>((copied, unchanged))
+    })?;
+    clean::record_managed(dest_dir, &copied.0)?;
+    Ok(copied)
+}
+
+/// Picks the directory under `dest_dir` that each of `source_dirs` is copied into.
+///
+/// A single root is copied straight into `dest_dir`. With several, each gets a subdirectory
+/// named after the root (`protobuf`, `proto`, ...), suffixed with `-2`, `-3`, ... when two roots
+/// share a name, so identically named files in different roots never overwrite each other.
+pub fn staging_dirs(dest_dir: &Path, source_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    if source_dirs.len() <= 1 {
+        return vec![dest_dir.to_path_buf(); source_dirs.len()];
+    }
+    let mut taken = HashSet::new();
+    let mut dirs = Vec::with_capacity(source_dirs.len());
+    for source_dir in source_dirs {
+        let source_dir = fs::canonicalize(source_dir).unwrap_or_else(|_| source_dir.clone());
+        let base = source_dir.file_name().map_or_else(
+            || "root".to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while !taken.insert(name.clone()) {
+            suffix += 1;
+            name = format!("{base}-{suffix}");
+        }
+        dirs.push(dest_dir.join(name));
+    }
+    dirs
+}
+
+/// Pairs each of `files` with the path [`copy_protos`] would copy it to, without touching the
+/// filesystem.
+pub fn plan_copies(
+    source_dir: &Path,
+    dest_dir: &Path,
+    files: &[PathBuf],
+) -> Result<Vec<(PathBuf, PathBuf)>, BuildError> {
+    plan_copies_stripped(source_dir, dest_dir, files, Path::new(""))
+}
+
+/// Pairs each of `files` with the path [`copy_protos_stripped`] would copy it to. Fails when
+/// stripping the prefix would put two files in the same place.
+pub fn plan_copies_stripped(
+    source_dir: &Path,
+    dest_dir: &Path,
+    files: &[PathBuf],
+    strip_prefix: &Path,
+) -> Result<Vec<(PathBuf, PathBuf)>, BuildError> {
+    prefix::validate(strip_prefix)?;
+    let source_dir = &fs::canonicalize(source_dir)?;
+    let mut taken = HashMap::new();
+    let mut plan = Vec::with_capacity(files.len());
+    for path in files {
+        let to = destination(source_dir, dest_dir, path, strip_prefix)?;
+        if let Some(other) = taken.insert(to.clone(), path) {
+            return Err(BuildError::Config(format!(
+                "`{}` and `{}` would both be copied to `{}` once `{}` is stripped",
+                other.display(),
+                path.display(),
+                to.display(),
+                strip_prefix.display()
+            )));
+        }
+        plan.push((path.clone(), to));
+    }
+    Ok(plan)
+}
+
+/// `source_dir` must already be canonical; `path` is canonicalized here so callers can pass
+/// either form.
+fn destination(
+    source_dir: &Path,
+    dest_dir: &Path,
+    path: &Path,
+    strip_prefix: &Path,
+) -> Result<PathBuf, BuildError> {
+    let path = fs::canonicalize(path)?;
+    let relative = path.strip_prefix(source_dir).map_err(|_| {
+        BuildError::Config(format!(
+            "`{}` is not inside `{}`",
+            path.display(),
+            source_dir.display()
+        ))
+    })?;
+    Ok(dest_dir.join(prefix::strip(relative, strip_prefix)))
+}
+
+/// Returns the destination and whether it had to be written.
+fn copy_proto(
+    path: &Path,
+    dest_path: &Path,
+    strip_prefix: &Path,
+) -> Result<(PathBuf, bool), BuildError> {
+    let contents = prefix::staged_contents(path, strip_prefix)?;
+    let unchanged = is_unchanged(&contents, dest_path)?;
+    if unchanged {
+        log(
+            LogLevel::Debug,
+            format_args!("unchanged, not copying {}", dest_path.display()),
+        );
+    } else {
+        log(
+            LogLevel::Debug,
+            format_args!("copying {} -> {}", path.display(), dest_path.display()),
+        );
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(dest_path, &contents)?;
+        // Carry the source mtime over so the watched output directory only looks changed
+        // when somebody edits it, not every time the build script copies into it.
+        let modified = fs::metadata(path)?.modified()?;
+        fs::File::options()
+            .write(true)
+            .open(dest_path)?
+            .set_modified(modified)?;
+    }
+    Ok((dest_path.to_path_buf(), !unchanged))
+}
+
+/// Whether `dest` already holds exactly `contents`, in which case copying would only bump its
+/// mtime and invite needless downstream rebuilds.
+fn is_unchanged(contents: &[u8], dest: &Path) -> Result<bool, BuildError> {
+    match fs::read(dest) {
+        Ok(existing) => Ok(existing == contents),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Splits a path-list setting with [`env::split_paths`], so it uses `;` on Windows (where
+/// `C:\proto` contains a colon) and `:` elsewhere, exactly like `PATH`. Empty entries are
+/// dropped.
+pub fn split_path_list(list: &OsStr) -> Vec<PathBuf> {
+    env::split_paths(list)
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect()
+}
+
+/// Asks Cargo to re-run the build when anything under `source_dir` or `out_dir` changes.
+///
+/// The directories are watched as a whole so that new files and edits to transitively
+/// imported protos trigger regeneration, not just the files compiled directly.
+pub fn track_dependencies(source_dir: &Path, protos: &[PathBuf], out_dir: &Path) {
+    println!("cargo:rerun-if-changed={}", source_dir.display());
+    for path in protos {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    println!("cargo:rerun-if-changed={}", out_dir.display());
+}
+
+/// What a call to [`compile_protos`] produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOutput {
+    /// Names of the generated top-level modules.
+    pub modules: Vec<String>,
+    /// Every file written: the generated Rust, the module indexes and the descriptor set.
+    pub files: Vec<PathBuf>,
+}
+
+/// Generates Rust code for `files`, resolving imports against `includes`, and writes a
+/// [`MODULE_INDEX`] alongside it.
+///
+/// Returns the names of the generated top-level modules; wrapping the [`module_file_name`] of
+/// each in `pub mod <name> { include!(...); }` reproduces the index. Files that declare no
+/// package are reported with a warning and land in [`DEFAULT_MODULE`].
+pub fn compile(
+    files: &[PathBuf],
+    includes: &[PathBuf],
+    config: &CompileConfig,
+) -> Result<Vec<String>, BuildError> {
+    compile_protos(files, includes, config).map(|output| output.modules)
+}
+
+/// `file` relative to the first of `includes` holding it, or the whole path if none does.
+fn include_relative<'a>(file: &'a Path, includes: &[PathBuf]) -> &'a Path {
+    includes
+        .iter()
+        .find_map(|dir| file.strip_prefix(dir).ok())
+        .unwrap_or(file)
+}
+
+/// The configured out dir, else Cargo's `OUT_DIR`.
+pub(crate) fn resolve_out_dir(config: &CompileConfig) -> Result<PathBuf, BuildError> {
+    match &config.out_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => env::var_os("OUT_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| BuildError::Config("OUT_DIR is not set".to_string())),
+    }
+}
+
+/// Like [`compile`], but also reports every file that was generated.
+pub fn compile_protos(
+    files: &[PathBuf],
+    includes: &[PathBuf],
+    config: &CompileConfig,
+) -> Result<CompileOutput, BuildError> {
+    compile_protos_with(files, includes, config, None)
+}
+
+/// [`compile_protos`], failing with the [`check_protoc`] error once protoc is about to run if it
+/// is older than `min_protoc_version`. Cache hits and current vendored code never look at it.
+pub(crate) fn compile_protos_with(
+    files: &[PathBuf],
+    includes: &[PathBuf],
+    config: &CompileConfig,
+    min_protoc_version: Option<ProtocVersion>,
+) -> Result<CompileOutput, BuildError> {
+    let out_dir = resolve_out_dir(config)?;
+    fs::create_dir_all(&out_dir)?;
+
+    // Message settings go on prost's own config, which tonic only adds its service generator to,
+    // so a messages-only build generates exactly the same message code.
+    let mut prost_config = tonic_prost_build::Config::new();
+    prost_config.out_dir(&out_dir);
+    if config.proto3_optional {
+        prost_config.protoc_arg("--experimental_allow_proto3_optional");
+    }
+    if !config.doc_comments {
+        prost_config.disable_comments(["."]);
+    }
+    for (path, attribute) in &config.type_attributes {
+        prost_config.type_attribute(path, attribute);
+    }
+    for (path, attribute) in &config.field_attributes {
+        prost_config.field_attribute(path, attribute);
+    }
+    for path in &config.boxed {
+        prost_config.boxed(path);
+    }
+    // Explicit extern paths come last so they can still override the well-known type mapping.
+    for (proto_path, rust_path) in wkt::extern_paths(&config.well_known_types)
+        .iter()
+        .chain(&config.extern_paths)
+    {
+        prost_config.extern_path(proto_path, rust_path);
+    }
+    // Checking boxed paths needs the descriptors, so keep a private copy when none was asked for.
+    let descriptor_set = match &config.descriptor_set {
+        Some(path) => Some(path.clone()),
+        // Keep it out of a vendor directory, which is under version control.
+        None if !config.boxed.is_empty() => Some(
+            env::var_os("OUT_DIR")
+                .filter(|_| config.vendor)
+                .map_or_else(|| out_dir.clone(), PathBuf::from)
+                .join(BOXED_CHECK_DESCRIPTOR),
+        ),
+        None => None,
+    };
+    if let Some(path) = &descriptor_set {
+        prost_config.file_descriptor_set_path(path);
+    }
+
+    // protoc only matches files to include paths by literal prefix, so give it canonical forms of
+    // both; include paths that don't exist are passed through for protoc to report.
+    // Include paths keep their order, since the first one holding an import wins, and only
+    // repeats are dropped. Files are sorted by their path under the include holding them, so
+    // the generated code and the cache key don't depend on the order they were passed in.
+    let mut seen = HashSet::new();
+    let includes: Vec<PathBuf> = includes
+        .iter()
+        .map(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+        .filter(|dir| seen.insert(dir.clone()))
+        .collect();
+    let mut files = files
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    files.sort_by(|a, b| include_relative(a, &includes).cmp(include_relative(b, &includes)));
+    let mut packages = Vec::with_capacity(files.len());
+    for file in &files {
+        let package = parse::package(&parse::read_proto(file)?);
+        if package.is_none() {
+            println!(
+                "cargo:warning=`{}` declares no package, its types are generated into module `{DEFAULT_MODULE}`",
+                file.display()
+            );
+        }
+        packages.push(package);
+    }
+    let generated_packages = generated_packages(&files, &includes, config)?;
+    if config.messages_only {
+        for file in &files {
+            for service in parse::services(&parse::read_proto(file)?) {
+                println!(
+                    "cargo:warning=`{}` declares service `{service}`, which messages-only mode leaves out",
+                    file.display()
+                );
+            }
+        }
+    }
+    let services = if config.client_helpers
+        && config.build_client
+        && !config.grpc_web
+        && !config.messages_only
+    {
+        helpers::services_by_package(&files, &packages)?
+    } else {
+        BTreeMap::new()
+    };
+    // prost's `.` above covers messages, enums and fields, but tonic only leaves out the docs of
+    // services and methods it is given by name.
+    let mut undocumented = Vec::new();
+    if !config.doc_comments && !config.messages_only {
+        for (file, package) in files.iter().zip(&packages) {
+            let contents = parse::read_proto(file)?;
+            let qualify = |name: String| match package {
+                Some(package) => format!("{package}.{name}"),
+                None => name,
+            };
+            undocumented.extend(parse::services(&contents).into_iter().map(qualify));
+            undocumented.extend(
+                parse::rpcs(&contents)
+                    .into_iter()
+                    .map(|(service, method)| qualify(format!("{service}.{method}"))),
+            );
+        }
+    }
+    let extra_includes: Vec<(Option<String>, String)> = services
+        .keys()
+        .map(|package| {
+            (
+                package.clone(),
+                client_helpers_file_name(package.as_deref()),
+            )
+        })
+        .collect();
+
+    let checksum = if config.vendor {
+        let checksum = vendor::checksum(&files, &includes, config)?;
+        let output = expected_output(&out_dir, &generated_packages, &extra_includes, config);
+        if vendor::is_current(&output.files, &checksum)? {
+            log(
+                LogLevel::Info,
+                format_args!(
+                    "vendored code in {} is up to date, skipping protoc",
+                    out_dir.display()
+                ),
+            );
+            return Ok(output);
+        }
+        Some(checksum)
+    } else {
+        None
+    };
+
+    let fingerprint = cache::Fingerprint::new(&files, &includes, config)?;
+    let previous = if config.vendor {
+        None
+    } else {
+        cache::load(&out_dir)?.filter(cache::Cached::outputs_exist)
+    };
+    if let Some(previous) = previous
+        .as_ref()
+        .filter(|previous| config.cache && previous.digest == fingerprint.digest())
+    {
+        log(
+            LogLevel::Info,
+            format_args!("inputs unchanged since the last compile, skipping protoc"),
+        );
+        warn_unmatched_boxed(descriptor_set.as_deref(), &config.boxed)?;
+        return Ok(previous.output.clone());
+    }
+    let selected = if config.incremental {
+        incremental_subset(
+            &files,
+            &packages,
+            &includes,
+            &fingerprint,
+            previous.as_ref(),
+            descriptor_set.is_some(),
+        )
+    } else {
+        files.clone()
+    };
+
+    log(LogLevel::Debug, format_args!("compile protos {selected:?}"));
+    log(
+        LogLevel::Debug,
+        format_args!("compile includes {includes:?}"),
+    );
+    log(
+        LogLevel::Info,
+        format_args!(
+            "compiling {} proto(s) into {}",
+            selected.len(),
+            out_dir.display()
+        ),
+    );
+    if !selected.is_empty() {
+        protoc::require_protoc(min_protoc_version)?;
+        let compiled = if config.messages_only {
+            prost_config.compile_protos(&selected, &includes)
+        } else {
+            tonic_prost_build::configure()
+                .out_dir(&out_dir)
+                .build_server(config.build_server)
+                .build_client(config.build_client)
+                .build_transport(!config.grpc_web)
+                .disable_comments(&undocumented)
+                .compile_with_config(prost_config, &selected, &includes)
+        };
+        compiled.map_err(|err| BuildError::Compile(err.to_string()))?;
+    }
+    warn_unmatched_boxed(descriptor_set.as_deref(), &config.boxed)?;
+
+    helpers::write_client_helpers(&out_dir, &services)?;
+    modules::write_module_index_with(&out_dir, &generated_packages, &extra_includes)?;
+    if let WellKnownTypes::Native { .. } = config.well_known_types {
+        fs::write(out_dir.join(NATIVE_SHIM), wkt::NATIVE_SHIM_SOURCE)?;
+    }
+    if !config.module_map.is_empty() {
+        write_module_map(&out_dir, &generated_packages, &config.module_map)?;
+    }
+    let output = expected_output(&out_dir, &generated_packages, &extra_includes, config);
+    if config.doc_comments {
+        docs::balance_code_fences(&output.files)?;
+    }
+    if let Some(rustfmt) = &config.rustfmt {
+        format_generated(rustfmt, &output.files)?;
+    }
+    if let Some(checksum) = checksum {
+        vendor::stamp(&output.files, &checksum)?;
+        return Ok(output);
+    }
+    cache::store(&out_dir, &fingerprint, &output)?;
+    Ok(output)
+}
+
+/// The packages prost writes code for: those of `files` plus those of every proto the files
+/// import from `includes`, directly or not, since the module index has to declare imported
+/// packages too for `super::` paths into them to resolve. Packages that are extern paths get no
+/// code and are left out, as is `google.protobuf`, which prost maps to `prost_types`, and files
+/// that declare no types: a service alone only produces code with tonic, not in a messages-only
+/// build. Sorted, without repeats.
+fn generated_packages(
+    files: &[PathBuf],
+    includes: &[PathBuf],
+    config: &CompileConfig,
+) -> Result<Vec<Option<String>>, BuildError> {
+    let is_extern = |package: &str| {
+        let path = format!(".{package}");
+        package == "google.protobuf"
+            || config.extern_paths.iter().any(|(proto_path, _)| {
+                path == *proto_path || path.starts_with(&format!("{proto_path}."))
+            })
+    };
+    let mut generated = BTreeSet::new();
+    let graph = imports::ImportGraph::build(files, includes)?;
+    for file in graph.files() {
+        let contents = parse::read_proto(file)?;
+        let has_services = !config.messages_only && !parse::services(&contents).is_empty();
+        if parse::top_level_types(&contents).is_empty() && !has_services {
+            continue;
+        }
+        let package = parse::package(&contents);
+        if !package.as_deref().is_some_and(is_extern) {
+            generated.insert(package);
+        }
+    }
+    Ok(generated.into_iter().collect())
+}
+
+/// Every file a full compile of `packages` into `out_dir` writes, `extra_includes` being the
+/// helper files included next to them.
+fn expected_output(
+    out_dir: &Path,
+    packages: &[Option<String>],
+    extra_includes: &[(Option<String>, String)],
+    config: &CompileConfig,
+) -> CompileOutput {
+    let modules = modules::module_names(packages);
+    let mut files = vec![out_dir.join(MODULE_INDEX)];
+    files.extend(
+        modules
+            .iter()
+            .map(|module| out_dir.join(module_file_name(module))),
+    );
+    files.extend(
+        packages
+            .iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|package| out_dir.join(generated_file_name(package.as_deref()))),
+    );
+    files.extend(extra_includes.iter().map(|(_, file)| out_dir.join(file)));
+    if let WellKnownTypes::Native { .. } = config.well_known_types {
+        files.push(out_dir.join(NATIVE_SHIM));
+    }
+    if !config.module_map.is_empty() {
+        files.push(out_dir.join(MODULE_MAP_INDEX));
+    }
+    files.extend(config.descriptor_set.clone());
+    CompileOutput { modules, files }
+}
+
+/// The part of `files` an incremental compile hands to protoc: every file whose hash differs
+/// from `previous`, every file importing one of those, and every other file in the same
+/// packages, since prost writes one Rust file per package.
+///
+/// Anything that prevents working out that set safely falls back to all of `files`, with a log
+/// line saying why.
+fn incremental_subset(
+    files: &[PathBuf],
+    packages: &[Option<String>],
+    includes: &[PathBuf],
+    fingerprint: &cache::Fingerprint,
+    previous: Option<&cache::Cached>,
+    writes_descriptor_set: bool,
+) -> Vec<PathBuf> {
+    let full = |reason: &dyn std::fmt::Display| {
+        log(
+            LogLevel::Info,
+            format_args!("incremental: {reason}, compiling all protos"),
+        );
+        files.to_vec()
+    };
+    if writes_descriptor_set {
+        return full(&"the descriptor set has to describe every proto");
+    }
+    let Some(previous) = previous.filter(|previous| previous.config == fingerprint.config) else {
+        return full(&"no previous compile with this configuration");
+    };
+    let current: HashSet<&PathBuf> = fingerprint.files.iter().map(|(file, _)| file).collect();
+    if previous.files.keys().any(|file| !current.contains(file)) {
+        return full(&"protos were removed since the last compile");
+    }
+    let graph = match imports::ImportGraph::build(files, includes) {
+        Ok(graph) => graph,
+        Err(err) => return full(&format_args!("could not resolve the import graph ({err})")),
+    };
+
+    let changed: HashSet<PathBuf> = fingerprint
+        .files
+        .iter()
+        .filter(|(file, digest)| previous.files.get(file) != Some(digest))
+        .map(|(file, _)| file.clone())
+        .collect();
+    let affected = graph.dependents(&changed);
+    let affected_packages: HashSet<&Option<String>> = files
+        .iter()
+        .zip(packages)
+        .filter(|(file, _)| affected.contains(*file))
+        .map(|(_, package)| package)
+        .collect();
+    let subset: Vec<PathBuf> = files
+        .iter()
+        .zip(packages)
+        .filter(|(_, package)| affected_packages.contains(package))
+        .map(|(file, _)| file.clone())
+        .collect();
+    log(
+        LogLevel::Info,
+        format_args!(
+            "incremental: {} changed, {} of {} proto(s) affected",
+            changed.len(),
+            subset.len(),
+            files.len()
+        ),
+    );
+    subset
+}
+
+/// Private descriptor set written when boxed paths need checking and no `descriptor_set` was
+/// configured.
+const BOXED_CHECK_DESCRIPTOR: &str = ".proto-gen-boxed-check.bin";
+
+/// Warns about every boxed path that selects no field, since prost silently ignores those.
+fn warn_unmatched_boxed(descriptor_set: Option<&Path>, boxed: &[String]) -> Result<(), BuildError> {
+    let Some(descriptor_set) = descriptor_set.filter(|_| !boxed.is_empty()) else {
+        return Ok(());
+    };
+    let bytes = match fs::read(descriptor_set) {
+        Ok(bytes) => bytes,
+        // Only possible on a cache hit after someone deleted it; the next real compile rechecks.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    for path in fields::unmatched_field_paths(&bytes, boxed)? {
+        println!("cargo:warning=boxed path `{path}` does not match any field");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn discovery_finds_nested_protos_and_skips_other_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        write(&root.join("top.proto"), "");
+        write(&root.join("a/b/nested.proto"), "");
+        write(&root.join("a/notes.txt"), "");
+        write(&root.join("a/proto"), "");
+
+        let root = fs::canonicalize(root).unwrap();
+        assert_eq!(
+            discover_protos(&root).unwrap(),
+            [root.join("a/b/nested.proto"), root.join("top.proto")]
+        );
+    }
+
+    #[test]
+    fn discovery_order_does_not_depend_on_creation_order() {
+        let names = [
+            "b/z.proto",
+            "a.proto",
+            "b/a.proto",
+            "c/d/e.proto",
+            "ab.proto",
+        ];
+        let discover = |names: &mut dyn Iterator<Item = &&str>| {
+            let tmp = tempfile::tempdir().unwrap();
+            for name in names {
+                write(&tmp.path().join(name), "");
+            }
+            let root = fs::canonicalize(tmp.path()).unwrap();
+            let found = discover_protos(&root).unwrap();
+            found
+                .iter()
+                .map(|path| path.strip_prefix(&root).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+
+        let forward = discover(&mut names.iter());
+        assert_eq!(forward, discover(&mut names.iter().rev()));
+        let mut sorted = forward.clone();
+        sorted.sort();
+        assert_eq!(forward, sorted);
+    }
+
+    #[test]
+    fn discovery_under_a_missing_root_is_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(discover_protos(&tmp.path().join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn discovery_lists_a_file_reached_through_a_symlink_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        write(&root.join("a/x.proto"), "");
+        std::os::unix::fs::symlink(root.join("a"), root.join("alias")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("a/loop")).unwrap();
+
+        assert_eq!(discover_protos(&root).unwrap(), [root.join("a/x.proto")]);
+    }
+
+    #[test]
+    fn copying_a_file_from_outside_the_source_dir_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stray = write(&tmp.path().join("stray.proto"), "");
+        fs::create_dir(tmp.path().join("src")).unwrap();
+
+        let err = copy_protos(&tmp.path().join("src"), &tmp.path().join("dest"), &[stray]);
+        assert!(matches!(err, Err(BuildError::Config(msg)) if msg.contains("is not inside")));
+    }
+
+    #[test]
+    fn identical_copy_keeps_the_destination_mtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dest) = (tmp.path().join("src"), tmp.path().join("dest"));
+        let files = [write(&src.join("a/x.proto"), "package a;")];
+        copy_protos(&src, &dest, &files).unwrap();
+
+        // Move the mtime away from the source's, which a rewrite would copy back over it.
+        let marked = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        let copied = fs::File::options()
+            .write(true)
+            .open(dest.join("a/x.proto"))
+            .unwrap();
+        copied.set_modified(marked).unwrap();
+
+        let (_, unchanged) = stage_protos(&src, &dest, &files, Path::new("")).unwrap();
+        assert_eq!(unchanged, 1);
+        let modified = fs::metadata(dest.join("a/x.proto")).unwrap().modified();
+        assert_eq!(modified.unwrap(), marked);
+    }
+
+    #[test]
+    fn parallel_copy_of_a_large_tree_arrives_intact() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dest) = (tmp.path().join("src"), tmp.path().join("dest"));
+        // Few directories for many files, so the workers race to create the same parents.
+        let files: Vec<_> = (0..500)
+            .map(|i| {
+                let path = src.join(format!("d{}/f{i}.proto", i % 7));
+                write(&path, &format!("package d{};\n// {i}\n", i % 7))
+            })
+            .collect();
+
+        let copied = copy_protos(&src, &dest, &files).unwrap();
+
+        assert_eq!(copied.len(), 500);
+        for (i, path) in copied.iter().enumerate() {
+            assert_eq!(path, &dest.join(format!("d{}/f{i}.proto", i % 7)));
+            let contents = fs::read_to_string(path).unwrap();
+            assert_eq!(contents, format!("package d{};\n// {i}\n", i % 7));
+        }
+    }
+
+    #[test]
+    fn path_list_keeps_a_drive_letter_in_one_entry() {
+        // Written with the host's separator, so Windows sees `C:\proto` and not `C` and `\proto`.
+        let (list, expected): (&str, &[&str]) = if cfg!(windows) {
+            (
+                r"C:\proto;;D:\third_party",
+                &[r"C:\proto", r"D:\third_party"],
+            )
+        } else {
+            ("/c/proto::/d/third_party", &["/c/proto", "/d/third_party"])
+        };
+        let expected: Vec<PathBuf> = expected.iter().map(PathBuf::from).collect();
+        assert_eq!(split_path_list(OsStr::new(list)), expected);
+    }
+
+    #[test]
+    fn files_sharing_a_name_in_different_dirs_are_both_copied() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dest) = (tmp.path().join("src"), tmp.path().join("dest"));
+        let a = write(&src.join("a/x.proto"), "package a;");
+        let b = write(&src.join("b/x.proto"), "package b;");
+
+        let copied = copy_protos(&src, &dest, &[a, b]).unwrap();
+
+        assert_eq!(copied, [dest.join("a/x.proto"), dest.join("b/x.proto")]);
+        assert_eq!(
+            fs::read_to_string(dest.join("a/x.proto")).unwrap(),
+            "package a;"
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("b/x.proto")).unwrap(),
+            "package b;"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Serializing builds that share a staging or out dir.
+//!
+//! Cargo already gives every crate its own `OUT_DIR`, so the default layout never collides.
+//! Collisions come from directories shared on purpose, such as one staging tree or generated
+//! dir for a whole workspace, and making those unique per crate would defeat the point of
+//! configuring them. Instead each build takes an advisory lock on the directories it writes
+//! and waits its turn. The OS drops the lock when the holder exits, even if it crashes, so a
+//! leftover lock file never blocks anyone. A vendor directory is committed, so its lock file
+//! lives in the system temp dir instead, named after the directory.
+
+use std::env;
+use std::fs::{self, File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use crate::{log, BuildError, LogLevel};
+
+/// Lock file created in each directory a build writes to, other than a vendor directory.
+pub const LOCK_FILE: &str = ".proto-gen.lock";
+
+/// How long to wait for another build before giving up, unless configured otherwise.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Holds the lock on a directory until dropped, which also happens when the build fails.
+#[derive(Debug)]
+pub(crate) struct DirLock {
+    file: File,
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock as well; unlocking first just makes it prompt.
+        let _ = self.file.unlock();
+    }
+}
+
+/// Locks every one of `dirs` and `committed`, creating them as needed, and returns the guards.
+/// The lock files of `committed` directories go in the system temp dir, so locking leaves them
+/// untouched.
+///
+/// The lock files are taken in a fixed order, so two builds sharing several directories can't
+/// each hold one while waiting for the other; the same directory listed twice is locked once.
+pub(crate) fn lock_dirs(
+    dirs: &[PathBuf],
+    committed: &[PathBuf],
+    timeout: Duration,
+) -> Result<Vec<DirLock>, BuildError> {
+    let mut lock_files = Vec::with_capacity(dirs.len() + committed.len());
+    for dir in dirs {
+        fs::create_dir_all(dir)?;
+        lock_files.push(fs::canonicalize(dir)?.join(LOCK_FILE));
+    }
+    for dir in committed {
+        fs::create_dir_all(dir)?;
+        let digest = Sha256::digest(fs::canonicalize(dir)?.to_string_lossy().as_bytes());
+        lock_files.push(env::temp_dir().join(format!("proto-gen-{:x}.lock", digest)));
+    }
+    lock_files.sort();
+    lock_files.dedup();
+    lock_files
+        .iter()
+        .map(|path| lock_file(path, timeout))
+        .collect()
+}
+
+fn lock_file(path: &Path, timeout: Duration) -> Result<DirLock, BuildError> {
+    let file = File::options()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(DirLock { file }),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        if started.elapsed() >= timeout {
+            return Err(BuildError::LockTimeout(path.to_path_buf(), timeout));
+        }
+        if !waiting {
+            log(
+                LogLevel::Info,
+                format_args!("waiting for another build to release {}", path.display()),
+            );
+            waiting = true;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::BuildError;
+
+/// How chatty the build is. Messages go out as `cargo:warning` lines because that is the only
+/// build-script output Cargo shows without `-vv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = BuildError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "" => Ok(LogLevel::Off),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(BuildError::Config(format!(
+                "log level must be `off`, `info` or `debug`, got `{value}`"
+            ))),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Off as u8);
+
+/// Sets the process-wide level used by [`log`].
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Emits `msg` if `level` is enabled.
+pub fn log(level: LogLevel, msg: impl fmt::Display) {
+    if level != LogLevel::Off && level as u8 <= LEVEL.load(Ordering::Relaxed) {
+        let tag = if level == LogLevel::Debug {
+            "debug"
+        } else {
+            "info"
+        };
+        println!("cargo:warning=[{tag}] {msg}");
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::BuildError;
+
+/// A file produced or staged by the build, as listed in a [`BuildManifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Artifact {
+    pub path: PathBuf,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+/// Everything a build handed to protoc and got back, for release tooling such as a JReleaser
+/// assembly step to pick up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BuildManifest {
+    /// The protos that were compiled, i.e. the staged copies when copying is enabled.
+    pub protos: Vec<Artifact>,
+    /// Generated Rust files, module indexes and the descriptor set.
+    pub outputs: Vec<Artifact>,
+    /// The encoded `FileDescriptorSet`, when one was requested. It is also listed in `outputs`.
+    pub descriptor_set: Option<PathBuf>,
+    /// Top-level Rust modules the generated code declares, one per root package.
+    pub modules: Vec<String>,
+}
+
+impl BuildManifest {
+    /// Collects the sizes of `protos` and `outputs`, which must all exist.
+    pub fn new(
+        protos: &[PathBuf],
+        outputs: &[PathBuf],
+        descriptor_set: Option<&Path>,
+    ) -> Result<Self, BuildError> {
+        Ok(BuildManifest {
+            protos: artifacts(protos)?,
+            outputs: artifacts(outputs)?,
+            descriptor_set: descriptor_set.map(Path::to_path_buf),
+            modules: Vec::new(),
+        })
+    }
+
+    /// Serializes the manifest as JSON to `path`, with every path rewritten relative to the
+    /// directory containing `path` so the manifest and its artifacts can be moved together.
+    pub fn write(&self, path: &Path) -> Result<(), BuildError> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let base = fs::canonicalize(dir)?;
+        let relocate = |artifact: &Artifact| -> Result<Artifact, BuildError> {
+            Ok(Artifact {
+                path: relative_to(&artifact.path, &base)?,
+                size: artifact.size,
+            })
+        };
+        let relative = BuildManifest {
+            protos: self.protos.iter().map(relocate).collect::<Result<_, _>>()?,
+            outputs: self
+                .outputs
+                .iter()
+                .map(relocate)
+                .collect::<Result<_, _>>()?,
+            descriptor_set: match &self.descriptor_set {
+                Some(path) => Some(relative_to(path, &base)?),
+                None => None,
+            },
+            modules: self.modules.clone(),
+        };
+        let json = serde_json::to_string_pretty(&relative).map_err(|err| {
+            BuildError::Config(format!("failed to serialize build manifest: {err}"))
+        })?;
+        fs::write(path, json + "\n")?;
+        Ok(())
+    }
+}
+
+/// Fails naming the missing file, since a bare "No such file or directory" gives no clue which
+/// expected output protoc didn't write.
+fn artifacts(paths: &[PathBuf]) -> Result<Vec<Artifact>, BuildError> {
+    paths
+        .iter()
+        .map(|path| {
+            let metadata = fs::metadata(path).map_err(|err| {
+                io::Error::new(err.kind(), format!("`{}`: {err}", path.display()))
+            })?;
+            Ok(Artifact {
+                path: path.clone(),
+                size: metadata.len(),
+            })
+        })
+        .collect()
+}
+
+/// `path` expressed relative to the canonical directory `base`, climbing out with `..` where
+/// needed. Paths on another root (a different Windows drive) stay absolute.
+fn relative_to(path: &Path, base: &Path) -> Result<PathBuf, BuildError> {
+    let path = fs::canonicalize(path)?;
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return Ok(path);
+    }
+    let mut relative: PathBuf = base
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(path.components().skip(common));
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_output_is_named_in_the_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("svc.rs");
+
+        let err = BuildManifest::new(&[], std::slice::from_ref(&missing), None).unwrap_err();
+
+        assert!(matches!(&err, BuildError::Io(io) if io.kind() == io::ErrorKind::NotFound));
+        assert!(err
+            .to_string()
+            .contains(&format!("`{}`", missing.display())));
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::BuildError;
+
+/// Name of the module index written next to the generated code.
+pub const MODULE_INDEX: &str = "generated.rs";
+
+/// The file prost writes for `package`: the dotted package name as-is, or `_` for files that
+/// declare no package.
+pub fn generated_file_name(package: Option<&str>) -> String {
+    format!("{}.rs", package.unwrap_or("_"))
+}
+
+/// Module that the types of files declaring no package are generated into.
+pub const DEFAULT_MODULE: &str = "unpackaged";
+
+/// The file [`write_module_index`] writes for the top-level module `module`, as returned by
+/// [`compile`](crate::compile). The `-` keeps it from ever clashing with a prost package file.
+pub fn module_file_name(module: &str) -> String {
+    format!("{}.proto-gen.rs", module.trim_start_matches("r#"))
+}
+
+/// The top-level module names [`write_module_index`] produces for `packages`, sorted.
+pub(crate) fn module_names(packages: &[Option<String>]) -> Vec<String> {
+    packages
+        .iter()
+        .map(|package| match package {
+            Some(package) => package.split('.').next().unwrap_or(package),
+            None => DEFAULT_MODULE,
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(module_ident)
+        .collect()
+}
+
+#[derive(Default)]
+struct Module {
+    files: Vec<String>,
+    children: BTreeMap<String, Module>,
+}
+
+/// Writes [`MODULE_INDEX`] into `out_dir`, nesting one `pub mod` per package component and
+/// `include!`-ing the matching generated file, so consumers never spell out file names.
+///
+/// The nesting mirrors the package hierarchy because prost refers to types in other packages
+/// through `super::` paths that assume exactly that layout. The body of each top-level module
+/// lives in its own [`module_file_name`] file, and files without a package end up in
+/// [`DEFAULT_MODULE`]. Returns the top-level module names, sorted.
+pub fn write_module_index(
+    out_dir: &Path,
+    packages: &[Option<String>],
+) -> Result<Vec<String>, BuildError> {
+    write_module_index_with(out_dir, packages, &[])
+}
+
+/// [`write_module_index`], also including each `(package, file)` pair of `extra` in the
+/// package's module after its generated code.
+pub(crate) fn write_module_index_with(
+    out_dir: &Path,
+    packages: &[Option<String>],
+    extra: &[(Option<String>, String)],
+) -> Result<Vec<String>, BuildError> {
+    let mut root = Module::default();
+    let generated: BTreeSet<_> = packages
+        .iter()
+        .map(|package| (package.clone(), generated_file_name(package.as_deref())))
+        .collect();
+    for (package, file) in generated.iter().chain(extra) {
+        let components: Vec<&str> = match package {
+            Some(package) => package.split('.').collect(),
+            None => vec![DEFAULT_MODULE],
+        };
+        let mut module = &mut root;
+        for component in components {
+            module = module.children.entry(component.to_string()).or_default();
+        }
+        if !module.files.contains(file) {
+            module.files.push(file.clone());
+        }
+    }
+
+    let mut index = String::from("// @generated by proto-gen. Do not edit.\n");
+    let mut names = Vec::with_capacity(root.children.len());
+    for (name, child) in &root.children {
+        let name = module_ident(name);
+        let file = module_file_name(&name);
+        let mut body = String::from("// @generated by proto-gen. Do not edit.\n");
+        render(child, 0, &mut body);
+        fs::write(out_dir.join(&file), body)?;
+        let _ = writeln!(index, "pub mod {name} {{\n    include!(\"{file}\");\n}}");
+        names.push(name);
+    }
+    fs::write(out_dir.join(MODULE_INDEX), index)?;
+    Ok(names)
+}
+
+fn render(module: &Module, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for file in &module.files {
+        let _ = writeln!(out, "{indent}include!(\"{file}\");");
+    }
+    for (name, child) in &module.children {
+        let _ = writeln!(out, "{indent}pub mod {} {{", module_ident(name));
+        render(child, depth + 1, out);
+        let _ = writeln!(out, "{indent}}}");
+    }
+}
+
+/// Turns a package component into a usable module name, escaping Rust keywords the same way
+/// prost does.
+pub(crate) fn module_ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do",
+        "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in",
+        "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe",
+        "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+    match name {
+        "self" | "super" | "crate" | "Self" => format!("{name}_"),
+        _ if KEYWORDS.contains(&name) => format!("r#{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// Name of the re-export file written when a module map is configured.
+pub const MODULE_MAP_INDEX: &str = "modules.rs";
+
+#[derive(Default)]
+struct Alias {
+    target: Option<String>,
+    children: BTreeMap<String, Alias>,
+}
+
+/// Writes [`MODULE_MAP_INDEX`] into `out_dir`: it includes [`MODULE_INDEX`] and then re-exports
+/// each mapped package under its short module path, so `com.acme.billing.v1` mapped to
+/// `billing` can be used as `pb::billing::InvoiceService`.
+///
+/// Two packages mapping to the same module, a module shadowing a generated package root, or a
+/// mapping for a package that wasn't compiled are all reported as errors.
+pub fn write_module_map(
+    out_dir: &Path,
+    packages: &[Option<String>],
+    map: &[(String, String)],
+) -> Result<PathBuf, BuildError> {
+    let roots: Vec<&str> = packages
+        .iter()
+        .map(|package| match package {
+            Some(package) => package.split('.').next().unwrap_or(package),
+            None => DEFAULT_MODULE,
+        })
+        .collect();
+
+    let mut root = Alias::default();
+    let mut claimed: BTreeMap<&str, &str> = BTreeMap::new();
+    for (package, module) in map {
+        if !packages.iter().flatten().any(|known| known == package) {
+            return Err(BuildError::Config(format!(
+                "module map entry `{package}` does not match any compiled package"
+            )));
+        }
+        if let Some(previous) = claimed.insert(module, package) {
+            return Err(BuildError::Config(format!(
+                "packages `{previous}` and `{package}` both map to module `{module}`"
+            )));
+        }
+        let segments: Vec<&str> = module.split("::").collect();
+        if roots.contains(&segments[0]) {
+            return Err(BuildError::Config(format!(
+                "module `{module}` for package `{package}` collides with generated package root `{}`",
+                segments[0]
+            )));
+        }
+
+        let mut alias = &mut root;
+        for segment in &segments {
+            alias = alias.children.entry(segment.to_string()).or_default();
+        }
+        if alias.target.is_some() || !alias.children.is_empty() {
+            return Err(BuildError::Config(format!(
+                "module `{module}` for package `{package}` overlaps another mapped module"
+            )));
+        }
+        alias.target = Some(package.clone());
+    }
+
+    let mut index =
+        format!("// @generated by proto-gen. Do not edit.\ninclude!(\"{MODULE_INDEX}\");\n");
+    render_aliases(&root, 0, &mut index);
+    let path = out_dir.join(MODULE_MAP_INDEX);
+    fs::write(&path, index)?;
+    Ok(path)
+}
+
+fn render_aliases(alias: &Alias, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    if let Some(package) = &alias.target {
+        let target: Vec<String> = package.split('.').map(module_ident).collect();
+        let _ = writeln!(
+            out,
+            "{indent}pub use {}{}::*;",
+            "super::".repeat(depth),
+            target.join("::")
+        );
+    }
+    for (name, child) in &alias.children {
+        let _ = writeln!(out, "{indent}pub mod {} {{", module_ident(name));
+        render_aliases(child, depth + 1, out);
+        let _ = writeln!(out, "{indent}}}");
+    }
+}
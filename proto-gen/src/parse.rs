@@ -0,0 +1,212 @@
+//! Just enough line-oriented proto parsing for the checks that run ahead of protoc.
+
+use std::fs;
+use std::path::Path;
+
+use crate::BuildError;
+
+/// Reads a proto as text, dropping a leading UTF-8 byte order mark so files saved by Windows
+/// editors parse and hash the same as everyone else's. Copies keep the original bytes; only
+/// readers go through here.
+pub(crate) fn read_proto(path: &Path) -> Result<String, BuildError> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    String::from_utf8(bytes.to_vec()).map_err(|_| BuildError::NotUtf8(path.to_path_buf()))
+}
+
+/// Lines of `contents` with surrounding whitespace removed, `//` comment lines dropped and
+/// `/* */` comments cut out, even across lines. Code on either side of a block comment comes out
+/// as two separate pieces.
+pub(crate) fn statements(contents: &str) -> impl Iterator<Item = &str> {
+    let mut in_block = false;
+    contents
+        .lines()
+        .flat_map(move |line| {
+            let mut pieces = Vec::new();
+            let mut rest = line;
+            loop {
+                if in_block {
+                    let Some(end) = rest.find("*/") else { break };
+                    rest = &rest[end + 2..];
+                    in_block = false;
+                    continue;
+                }
+                let line_comment = rest.find("//");
+                match rest.find("/*") {
+                    Some(start) if line_comment.is_none_or(|at| start < at) => {
+                        pieces.push(&rest[..start]);
+                        rest = &rest[start + 2..];
+                        in_block = true;
+                    }
+                    _ => {
+                        pieces.push(rest);
+                        break;
+                    }
+                }
+            }
+            pieces
+        })
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+}
+
+/// The value of the file's `package` statement, if it has one and it is a dotted identifier
+/// such as `acme.billing.v1`. Whatever follows the `;` is ignored.
+pub(crate) fn package(contents: &str) -> Option<String> {
+    statements(contents).find_map(|line| {
+        let rest = line
+            .strip_prefix("package")
+            .filter(|rest| rest.starts_with(char::is_whitespace))?;
+        let name = rest.split(';').next()?.trim();
+        let valid = name.split('.').all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        valid.then(|| name.to_string())
+    })
+}
+
+/// The names of the messages and enums declared at the top level of the file, not nested in
+/// another message.
+pub(crate) fn top_level_types(contents: &str) -> Vec<String> {
+    let mut depth = 0usize;
+    let mut types = Vec::new();
+    for line in statements(contents) {
+        if depth == 0 {
+            let declared = line
+                .strip_prefix("message")
+                .or_else(|| line.strip_prefix("enum"))
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .and_then(|rest| identifier(rest.trim_start()));
+            types.extend(declared);
+        }
+        depth += line.matches('{').count();
+        depth = depth.saturating_sub(line.matches('}').count());
+    }
+    types
+}
+
+/// The identifier at the start of `text`, if there is one.
+fn identifier(text: &str) -> Option<String> {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    (end > 0).then(|| text[..end].to_string())
+}
+
+/// The names of the services the file declares, in the order they appear.
+pub(crate) fn services(contents: &str) -> Vec<String> {
+    statements(contents)
+        .filter_map(|line| {
+            let rest = line.strip_prefix("service")?;
+            if !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            identifier(rest.trim_start())
+        })
+        .collect()
+}
+
+/// The `(service, method)` names of every `rpc` in the file's services.
+pub(crate) fn rpcs(contents: &str) -> Vec<(String, String)> {
+    let mut depth = 0usize;
+    let mut service = None;
+    let mut rpcs = Vec::new();
+    for line in statements(contents) {
+        if depth == 0 {
+            service = line
+                .strip_prefix("service")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .and_then(|rest| identifier(rest.trim_start()));
+        } else if let Some(service) = service.as_ref().filter(|_| depth == 1) {
+            let method = line
+                .strip_prefix("rpc")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .and_then(|rest| identifier(rest.trim_start()));
+            rpcs.extend(method.map(|method| (service.clone(), method)));
+        }
+        depth += line.matches('{').count();
+        depth = depth.saturating_sub(line.matches('}').count());
+    }
+    rpcs
+}
+
+/// The paths named by the file's `import` statements, including `import public` and
+/// `import weak`, in the order they appear.
+pub(crate) fn imports(contents: &str) -> Vec<String> {
+    statements(contents)
+        .filter_map(|line| {
+            let (start, end) = import_span(line)?;
+            Some(line[start..end].to_string())
+        })
+        .collect()
+}
+
+/// `contents` with the path of every `import` statement passed through `rewrite`, which returns
+/// `None` to leave a path alone. Everything else is kept byte for byte. Returns `None` when no
+/// path changed.
+pub(crate) fn rewrite_imports(
+    contents: &str,
+    rewrite: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let mut rewritten = String::with_capacity(contents.len());
+    let mut changed = false;
+    for line in contents.split_inclusive('\n') {
+        let replacement = import_span(line)
+            .and_then(|(start, end)| Some((start, end, rewrite(&line[start..end])?)));
+        match replacement {
+            Some((start, end, path)) => {
+                rewritten.push_str(&line[..start]);
+                rewritten.push_str(&path);
+                rewritten.push_str(&line[end..]);
+                changed = true;
+            }
+            None => rewritten.push_str(line),
+        }
+    }
+    changed.then_some(rewritten)
+}
+
+/// Byte range of the quoted path in `line`, if the line is an `import` statement.
+fn import_span(line: &str) -> Option<(usize, usize)> {
+    let trimmed = line.trim_start();
+    let rest = trimmed.strip_prefix("import")?;
+    let quote = rest.find(['"', '\''])?;
+    let modifier = rest[..quote].trim();
+    if !matches!(modifier, "" | "public" | "weak") {
+        return None;
+    }
+    // Both delimiters are ASCII, so the path starts one byte past the quote.
+    let delimiter = rest[quote..].chars().next()?;
+    let start = line.len() - rest.len() + quote + 1;
+    let end = start + line[start..].find(delimiter)?;
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_ignores_a_trailing_comment() {
+        let contents = "syntax = \"proto3\";\npackage foo.bar; // the billing API\n";
+        assert_eq!(package(contents).as_deref(), Some("foo.bar"));
+    }
+
+    #[test]
+    fn package_inside_a_block_comment_is_ignored() {
+        let contents = "/* Moved from\npackage old;\n*/\npackage new.v1;\n";
+        assert_eq!(package(contents).as_deref(), Some("new.v1"));
+        assert_eq!(
+            top_level_types("/*\nmessage Gone {}\n*/\nmessage Kept {}"),
+            ["Kept"]
+        );
+    }
+
+    #[test]
+    fn package_that_is_not_a_dotted_identifier_is_rejected() {
+        assert_eq!(package("package foo..bar;"), None);
+        assert_eq!(package("package 1foo;"), None);
+        assert_eq!(package("packages foo;"), None);
+    }
+}
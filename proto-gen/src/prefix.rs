@@ -0,0 +1,66 @@
+//! Dropping a leading directory such as `com/acme` from staged protos.
+
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::{parse, BuildError};
+
+/// Where protoc keeps the well-known types. Their imports are never rewritten, even when the
+/// stripped prefix happens to cover them.
+const WELL_KNOWN_DIR: &str = "google/protobuf/";
+
+/// Checks that `prefix` is a plain relative directory. An empty prefix strips nothing.
+pub(crate) fn validate(prefix: &Path) -> Result<(), BuildError> {
+    if prefix
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(())
+    } else {
+        Err(BuildError::Config(format!(
+            "strip prefix `{}` must be a relative directory such as `com/acme`",
+            prefix.display()
+        )))
+    }
+}
+
+/// `relative` without `prefix`. Paths outside the prefix are kept as they are.
+pub(crate) fn strip<'a>(relative: &'a Path, prefix: &Path) -> &'a Path {
+    relative.strip_prefix(prefix).unwrap_or(relative)
+}
+
+/// The bytes `path` is staged with: its own, except that imports under a non-empty `prefix`
+/// are pointed at their stripped location. A byte order mark survives the rewrite.
+pub(crate) fn staged_contents(path: &Path, prefix: &Path) -> Result<Vec<u8>, BuildError> {
+    let bytes = fs::read(path)?;
+    if prefix.as_os_str().is_empty() {
+        return Ok(bytes);
+    }
+    let (bom, text) = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        Some(text) => (&bytes[..3], text),
+        None => (&[][..], &bytes[..]),
+    };
+    let text = std::str::from_utf8(text).map_err(|_| BuildError::NotUtf8(path.to_path_buf()))?;
+    Ok(match rewrite_imports(text, prefix) {
+        Some(rewritten) => [bom, rewritten.as_bytes()].concat(),
+        None => bytes,
+    })
+}
+
+/// `contents` with every import under `prefix` rewritten to the path it has once the prefix is
+/// stripped, or `None` when nothing needed rewriting.
+fn rewrite_imports(contents: &str, prefix: &Path) -> Option<String> {
+    // Import paths always use `/`, whatever the host separator.
+    let mut prefix = prefix
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    prefix.push('/');
+    parse::rewrite_imports(contents, |import| {
+        if import.starts_with(WELL_KNOWN_DIR) {
+            return None;
+        }
+        import.strip_prefix(prefix.as_str()).map(String::from)
+    })
+}
@@ -0,0 +1,179 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::{log, BuildError, LogLevel};
+
+/// A protoc release number as printed by `protoc --version`.
+///
+/// Releases before 22 report themselves as `3.x.y` and later ones as `x.y`, which still compare
+/// in release order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FromStr for ProtocVersion {
+    type Err = BuildError;
+
+    /// Accepts `3.21.12`, `25.1` or a full `libprotoc 25.1` line; missing components are zero
+    /// and suffixes such as `-rc1` are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BuildError::Config(format!("invalid protoc version `{s}`"));
+        let version = s.split_whitespace().last().ok_or_else(invalid)?;
+        let mut parts = [0; 3];
+        for (slot, part) in parts.iter_mut().zip(version.split('.')) {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            *slot = digits.parse().map_err(|_| invalid())?;
+        }
+        let [major, minor, patch] = parts;
+        Ok(ProtocVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl fmt::Display for ProtocVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Runs `protoc --version` and parses the answer.
+pub fn protoc_version(protoc: &Path) -> Result<ProtocVersion, BuildError> {
+    let output = Command::new(protoc)
+        .arg("--version")
+        .output()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => BuildError::ProtocUnavailable(protoc.to_path_buf()),
+            _ => BuildError::Config(format!(
+                "failed to run `{} --version`: {err}",
+                protoc.display()
+            )),
+        })?;
+    if !output.status.success() {
+        return Err(BuildError::Config(format!(
+            "`{} --version` exited with {}",
+            protoc.display(),
+            output.status
+        )));
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse()
+}
+
+/// Validates the compiler before anything is generated.
+///
+/// An explicit `protoc` (the `PROTOC` variable) given as a path must be an existing executable
+/// file; a bare name such as `protoc` is looked up on the `PATH` like prost-build would. Its
+/// version is probed and logged at debug level, as is the default `protoc` whenever
+/// `min_version` asks for a check; a compiler older than `min_version` fails the build.
+pub fn check_protoc(
+    protoc: Option<&Path>,
+    min_version: Option<ProtocVersion>,
+) -> Result<Option<ProtocVersion>, BuildError> {
+    let is_path = |protoc: &Path| {
+        protoc
+            .parent()
+            .is_some_and(|dir| !dir.as_os_str().is_empty())
+    };
+    if let Some(protoc) = protoc.filter(|protoc| is_path(protoc)) {
+        if !protoc.is_file() {
+            return Err(BuildError::ProtocUnavailable(protoc.to_path_buf()));
+        }
+        if !is_executable(protoc)? {
+            return Err(BuildError::Config(format!(
+                "PROTOC `{}` is not executable",
+                protoc.display()
+            )));
+        }
+    } else if protoc.is_none() && min_version.is_none() {
+        return Ok(None);
+    }
+
+    let protoc = protoc.unwrap_or(Path::new("protoc"));
+    let version = protoc_version(protoc)?;
+    log(
+        LogLevel::Debug,
+        format_args!("using protoc {version} at {}", protoc.display()),
+    );
+    match min_version {
+        Some(min) if version < min => Err(BuildError::Config(format!(
+            "protoc {version} at `{}` is older than the required {min}; point PROTOC at a newer \
+             compiler or lower PROTO_MIN_PROTOC_VERSION",
+            protoc.display()
+        ))),
+        _ => Ok(Some(version)),
+    }
+}
+
+/// Fails with [`BuildError::ProtocUnavailable`] unless the compiler prost-build runs (`PROTOC`
+/// if set, else `protoc` from the `PATH`; nothing is bundled any more) can actually be started,
+/// so a missing compiler is reported plainly instead of from deep inside prost-build. Runs
+/// [`check_protoc`] on it first.
+pub(crate) fn require_protoc(min_version: Option<ProtocVersion>) -> Result<(), BuildError> {
+    let protoc = env::var_os("PROTOC").map(PathBuf::from);
+    if check_protoc(protoc.as_deref(), min_version)?.is_none() {
+        protoc_version(Path::new("protoc"))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> Result<bool, BuildError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(fs::metadata(path)?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> Result<bool, BuildError> {
+    // Windows has no execute bit; running the file is the only real test.
+    Ok(fs::metadata(path)?.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protoc_pointing_at_a_missing_path_is_unavailable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("no-such-protoc");
+
+        let err = check_protoc(Some(&missing), None).unwrap_err();
+        assert!(matches!(&err, BuildError::ProtocUnavailable(path) if *path == missing));
+        assert!(err.to_string().contains("set PROTOC to the path"));
+    }
+
+    #[test]
+    fn protoc_given_by_name_is_found_on_the_path() {
+        // A name that resolves nowhere fails the `--version` probe, not the file check.
+        let missing = Path::new("proto-gen-test-no-such-protoc");
+        assert!(matches!(
+            check_protoc(Some(missing), None),
+            Err(BuildError::ProtocUnavailable(path)) if path == missing
+        ));
+        if protoc_version(Path::new("protoc")).is_ok() {
+            assert!(check_protoc(Some(Path::new("protoc")), None)
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn version_probe_of_a_missing_binary_is_unavailable() {
+        let missing = Path::new("proto-gen-test-no-such-protoc");
+        assert!(matches!(
+            protoc_version(missing),
+            Err(BuildError::ProtocUnavailable(path)) if path == missing
+        ));
+    }
+}
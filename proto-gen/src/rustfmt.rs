@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{log, BuildError, LogLevel};
+
+/// Runs `rustfmt` over each `.rs` file in `files`.
+///
+/// A missing or broken `rustfmt` only produces a warning, since unformatted code still compiles.
+/// Each file is piped through stdin and only rewritten when rustfmt succeeds and changes it, so
+/// a file rustfmt chokes on keeps its exact contents and untouched files keep their mtime.
+pub fn format_generated(rustfmt: &Path, files: &[PathBuf]) -> Result<(), BuildError> {
+    let available = Command::new(rustfmt)
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !available {
+        println!(
+            "cargo:warning=`{}` is not available, leaving generated code unformatted",
+            rustfmt.display()
+        );
+        return Ok(());
+    }
+
+    for file in files
+        .iter()
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+    {
+        let original = fs::read(file)?;
+        match run(rustfmt, &original) {
+            Ok(formatted) if formatted != original => {
+                log(
+                    LogLevel::Debug,
+                    format_args!("formatted {}", file.display()),
+                );
+                fs::write(file, formatted)?;
+            }
+            Ok(_) => {}
+            Err(reason) => println!(
+                "cargo:warning=rustfmt failed on `{}`, keeping it as generated: {reason}",
+                file.display()
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn run(rustfmt: &Path, source: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(rustfmt)
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    // Dropping stdin at the end of the statement closes it so rustfmt sees EOF.
+    child
+        .stdin
+        .take()
+        .ok_or("stdin was not captured")?
+        .write_all(source)
+        .map_err(|err| err.to_string())?;
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+    if output.status.success() && !output.stdout.is_empty() {
+        Ok(output.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
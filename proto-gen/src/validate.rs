@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use crate::{parse, BuildError, ProtoError};
+
+/// Cheap sanity checks run ahead of protoc: each file must declare `syntax = "proto3";` and a
+/// `package`. Every violation across all files is collected rather than stopping at the first.
+pub fn validate_protos(files: &[PathBuf]) -> Result<(), Vec<ProtoError>> {
+    let mut errors = Vec::new();
+    for file in files {
+        let contents = match parse::read_proto(file) {
+            Ok(contents) => contents,
+            Err(BuildError::NotUtf8(_)) => {
+                errors.push(ProtoError {
+                    file: file.clone(),
+                    reason: "is not valid UTF-8 text".to_string(),
+                });
+                continue;
+            }
+            Err(err) => {
+                errors.push(ProtoError {
+                    file: file.clone(),
+                    reason: format!("could not be read: {err}"),
+                });
+                continue;
+            }
+        };
+
+        let has_syntax = parse::statements(&contents).any(|line| {
+            line.strip_prefix("syntax").is_some_and(|rest| {
+                let value = rest.trim_start().trim_start_matches('=').trim();
+                value.starts_with("\"proto3\"") || value.starts_with("'proto3'")
+            })
+        });
+        let has_package = parse::package(&contents).is_some();
+
+        if !has_syntax {
+            errors.push(ProtoError {
+                file: file.clone(),
+                reason: "missing `syntax = \"proto3\";` declaration".to_string(),
+            });
+        }
+        if !has_package {
+            errors.push(ProtoError {
+                file: file.clone(),
+                reason: "missing `package` statement".to_string(),
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
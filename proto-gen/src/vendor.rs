@@ -0,0 +1,176 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::imports::ImportGraph;
+use crate::{parse, BuildError, CompileConfig};
+
+/// First line of every vendored `.rs` file, followed by the checksum of the inputs it was
+/// generated from.
+pub const CHECKSUM_HEADER: &str = "// proto-gen-checksum: ";
+
+/// A checksum of the inputs that comes out the same on every machine: proto paths are taken
+/// relative to the include directory holding them, and the configuration is hashed without
+/// its local paths or caching switches. Protos imported from the include paths count as inputs.
+pub(crate) fn checksum(
+    files: &[PathBuf],
+    includes: &[PathBuf],
+    config: &CompileConfig,
+) -> Result<String, BuildError> {
+    let portable = CompileConfig {
+        descriptor_set: None,
+        out_dir: None,
+        cache: false,
+        incremental: false,
+        rustfmt: config.rustfmt.as_ref().map(|_| PathBuf::from("rustfmt")),
+        ..config.clone()
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update(format!("{portable:?}\n"));
+    for file in ImportGraph::build(files, includes)?.files() {
+        let name = includes
+            .iter()
+            .find_map(|dir| file.strip_prefix(dir).ok())
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let contents = parse::read_proto(file)?;
+        hasher.update(format!("\n{name}\n{}\n", contents.len()));
+        hasher.update(&contents);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether every `.rs` file in `files` exists and carries the header for `checksum`.
+pub(crate) fn is_current(files: &[PathBuf], checksum: &str) -> Result<bool, BuildError> {
+    for file in rust_files(files) {
+        let mut first_line = String::new();
+        match fs::File::open(file) {
+            Ok(handle) => BufReader::new(handle).read_line(&mut first_line)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        if first_line.trim_end().strip_prefix(CHECKSUM_HEADER) != Some(checksum) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Puts the header for `checksum` at the top of every `.rs` file in `files`, replacing any
+/// previous one.
+pub(crate) fn stamp(files: &[PathBuf], checksum: &str) -> Result<(), BuildError> {
+    for file in rust_files(files) {
+        let contents = fs::read_to_string(file)?;
+        fs::write(
+            file,
+            format!("{CHECKSUM_HEADER}{checksum}\n{}", strip_checksum(&contents)),
+        )?;
+    }
+    Ok(())
+}
+
+/// `contents` without its checksum header line, if it has one.
+pub fn strip_checksum(contents: &str) -> &str {
+    match contents.strip_prefix(CHECKSUM_HEADER) {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, body)| body),
+        None => contents,
+    }
+}
+
+/// A directory that is removed again when this is dropped, however the work in it ended.
+pub(crate) struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// Creates `path` afresh, dropping anything a previous run left there.
+    pub(crate) fn create(path: PathBuf) -> Result<Self, BuildError> {
+        match fs::remove_dir_all(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        fs::create_dir_all(&path)?;
+        Ok(ScratchDir(path))
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// How the `.rs` files among `files`, freshly generated into `generated`, differ from their
+/// copies in `vendor`, one line each. Checksum headers are ignored, and a vendored file that
+/// carries one but was not generated this time is reported as well.
+pub(crate) fn compare(
+    generated: &Path,
+    files: &[PathBuf],
+    vendor: &Path,
+) -> Result<Vec<String>, BuildError> {
+    let mut differences = Vec::new();
+    let mut names = Vec::new();
+    for file in rust_files(files) {
+        let name = file.strip_prefix(generated).unwrap_or(file);
+        let fresh = fs::read_to_string(file)?;
+        let vendored = match fs::read(vendor.join(name)) {
+            Ok(vendored) => vendored,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                differences.push(format!("`{}` is missing", name.display()));
+                names.push(name.to_path_buf());
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let vendored = String::from_utf8_lossy(&vendored);
+        let (fresh, vendored) = (strip_checksum(&fresh), strip_checksum(&vendored));
+        if fresh != vendored {
+            let line = fresh
+                .lines()
+                .zip(vendored.lines())
+                .take_while(|(a, b)| a == b)
+                .count()
+                + 1;
+            differences.push(format!("`{}` differs from line {line}", name.display()));
+        }
+        names.push(name.to_path_buf());
+    }
+    let entries = match fs::read_dir(vendor) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(differences),
+        Err(err) => return Err(err.into()),
+    };
+    let mut stale = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.strip_prefix(vendor).unwrap_or(&path).to_path_buf();
+        if path.extension().is_none_or(|ext| ext != "rs")
+            || !path.is_file()
+            || names.contains(&name)
+        {
+            continue;
+        }
+        let mut first_line = Vec::new();
+        BufReader::new(fs::File::open(&path)?).read_until(b'\n', &mut first_line)?;
+        if first_line.starts_with(CHECKSUM_HEADER.as_bytes()) {
+            stale.push(format!("`{}` is no longer generated", name.display()));
+        }
+    }
+    stale.sort();
+    differences.extend(stale);
+    Ok(differences)
+}
+
+fn rust_files(files: &[PathBuf]) -> impl Iterator<Item = &Path> {
+    files
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|file| file.extension().is_some_and(|ext| ext == "rs"))
+}
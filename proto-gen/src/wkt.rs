@@ -0,0 +1,95 @@
+//! Handling of the `google.protobuf` well-known types.
+
+/// How `google.protobuf.*` types are represented in the generated code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum WellKnownTypes {
+    /// Whatever prost does by default, which today is the same as [`Self::ProstTypes`].
+    #[default]
+    Default,
+    /// Use `::prost_types` for `.google.protobuf`, pinning prost's own default so the well-known
+    /// types are never generated locally.
+    ProstTypes,
+    /// Map `Timestamp` and `Duration` onto the newtypes in [`NATIVE_SHIM`], which wrap
+    /// `std::time::SystemTime` and `std::time::Duration`; everything else stays on
+    /// `::prost_types`. The shim must be included at `module`, e.g. `crate::wkt`.
+    ///
+    /// The conversions are lossy at the edges: timestamps outside the platform's `SystemTime`
+    /// range and negative durations fail to decode, and on platforms with coarser clocks
+    /// (100ns on Windows) sub-tick nanoseconds are dropped when encoding.
+    Native { module: String },
+}
+
+/// Name of the shim file written next to the generated code in [`WellKnownTypes::Native`] mode.
+pub const NATIVE_SHIM: &str = "wkt.rs";
+
+/// `(proto_path, rust_path)` pairs implementing `mode`, on top of the `.google.protobuf` to
+/// `::prost_types` mapping prost registers itself.
+pub(crate) fn extern_paths(mode: &WellKnownTypes) -> Vec<(String, String)> {
+    match mode {
+        WellKnownTypes::Default | WellKnownTypes::ProstTypes => Vec::new(),
+        WellKnownTypes::Native { module } => vec![
+            (
+                ".google.protobuf.Timestamp".to_string(),
+                format!("{module}::Timestamp"),
+            ),
+            (
+                ".google.protobuf.Duration".to_string(),
+                format!("{module}::Duration"),
+            ),
+        ],
+    }
+}
+
+/// Source of the [`NATIVE_SHIM`] file. Each newtype encodes exactly like its `prost_types`
+/// counterpart, converting on the way in and out.
+pub(crate) const NATIVE_SHIM_SOURCE: &str = concat!(
+    "// @generated by proto-gen. Do not edit.\n",
+    include_str!("wkt/shim.rs")
+);
+
+// The shim is compiled here as well, so its encoding is tested like any other module.
+#[cfg(test)]
+mod shim;
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use prost::Message;
+
+    use super::shim;
+
+    #[test]
+    fn native_timestamp_round_trips_through_the_prost_types_encoding() {
+        let raw = prost_types::Timestamp {
+            seconds: 1_700_000_000,
+            nanos: 123_456_789,
+        };
+
+        let native = shim::Timestamp::decode(raw.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(
+            native.0,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)
+        );
+        assert_eq!(native.encode_to_vec(), raw.encode_to_vec());
+        assert_eq!(native.encoded_len(), raw.encoded_len());
+    }
+
+    #[test]
+    fn native_duration_round_trips_and_rejects_negative_values() {
+        let raw = prost_types::Duration {
+            seconds: 90,
+            nanos: 5,
+        };
+
+        let native = shim::Duration::decode(raw.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(native.0, Duration::new(90, 5));
+        assert_eq!(native.encode_to_vec(), raw.encode_to_vec());
+
+        let negative = prost_types::Duration {
+            seconds: -1,
+            nanos: 0,
+        };
+        assert!(shim::Duration::decode(negative.encode_to_vec().as_slice()).is_err());
+    }
+}
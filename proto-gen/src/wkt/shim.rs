@@ -0,0 +1,111 @@
+use ::prost::bytes::{Buf, BufMut};
+use ::prost::encoding::{DecodeContext, WireType};
+use ::prost::{DecodeError, Message};
+
+/// `google.protobuf.Timestamp` as a `std::time::SystemTime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Timestamp(pub ::std::time::SystemTime);
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp(::std::time::UNIX_EPOCH)
+    }
+}
+
+impl From<::std::time::SystemTime> for Timestamp {
+    fn from(time: ::std::time::SystemTime) -> Self {
+        Timestamp(time)
+    }
+}
+
+impl From<Timestamp> for ::std::time::SystemTime {
+    fn from(time: Timestamp) -> Self {
+        time.0
+    }
+}
+
+impl Message for Timestamp {
+    fn encode_raw(&self, buf: &mut impl BufMut) {
+        ::prost_types::Timestamp::from(self.0).encode_raw(buf)
+    }
+
+    // prost 0.14.4 deprecates `DecodeError::new` without a replacement that 0.14.1 has.
+    #[allow(deprecated)]
+    fn merge_field(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        let mut raw = ::prost_types::Timestamp::from(self.0);
+        raw.merge_field(tag, wire_type, buf, ctx)?;
+        self.0 = ::std::time::SystemTime::try_from(raw)
+            .map_err(|_| DecodeError::new("timestamp is outside the SystemTime range"))?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        ::prost_types::Timestamp::from(self.0).encoded_len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// `google.protobuf.Duration` as a non-negative `std::time::Duration`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Duration(pub ::std::time::Duration);
+
+impl From<::std::time::Duration> for Duration {
+    fn from(duration: ::std::time::Duration) -> Self {
+        Duration(duration)
+    }
+}
+
+impl From<Duration> for ::std::time::Duration {
+    fn from(duration: Duration) -> Self {
+        duration.0
+    }
+}
+
+impl Duration {
+    fn to_raw(self) -> ::prost_types::Duration {
+        // Only durations beyond i64::MAX seconds fail; saturate rather than panic.
+        ::prost_types::Duration::try_from(self.0).unwrap_or(::prost_types::Duration {
+            seconds: i64::MAX,
+            nanos: 999_999_999,
+        })
+    }
+}
+
+impl Message for Duration {
+    fn encode_raw(&self, buf: &mut impl BufMut) {
+        self.to_raw().encode_raw(buf)
+    }
+
+    // prost 0.14.4 deprecates `DecodeError::new` without a replacement that 0.14.1 has.
+    #[allow(deprecated)]
+    fn merge_field(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut impl Buf,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        let mut raw = self.to_raw();
+        raw.merge_field(tag, wire_type, buf, ctx)?;
+        self.0 = ::std::time::Duration::try_from(raw)
+            .map_err(|_| DecodeError::new("negative durations are not supported"))?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.to_raw().encoded_len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
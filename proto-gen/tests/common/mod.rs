@@ -0,0 +1,28 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to `path`, creating its parent directories.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> PathBuf {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+    path.to_path_buf()
+}
+
+/// A proto3 file in `package` with `body` after the header.
+pub fn proto(package: &str, body: &str) -> String {
+    format!("syntax = \"proto3\";\npackage {package};\n{body}\n")
+}
+
+/// A builder over `source` that compiles in place into `out`, without caching.
+pub fn builder(source: &Path, out: &Path) -> proto_gen::ProtoGen {
+    proto_gen::ProtoGen::new()
+        .source_dir(source)
+        .out_dir(out)
+        .cache(false)
+}
+
+pub fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
@@ -0,0 +1,523 @@
+//! End-to-end runs of the pipeline; these need a `protoc` on the `PATH` or in `PROTOC`.
+
+mod common;
+
+use common::{builder, proto, read, write};
+use proto_gen::{BuildError, WellKnownTypes};
+
+#[test]
+fn module_index_declares_packages_imported_from_unlisted_protos() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(&source.join("a/x.proto"), proto("a", "message X {}"));
+    write(
+        &source.join("b/y.proto"),
+        proto("b", "import \"a/x.proto\";\nmessage Y { a.X x = 1; }"),
+    );
+    let list = write(&tmp.path().join("list.txt"), "# reviewed\nb/y.proto\n");
+
+    let manifest = builder(&source, &out).file_list(&list).run().unwrap();
+
+    assert_eq!(manifest.modules, ["a", "b"]);
+    assert!(read(&out.join("b.rs")).contains("super::a::X"));
+    assert!(read(&out.join("a.proto-gen.rs")).contains("include!(\"a.rs\");"));
+    let index = read(&out.join(proto_gen::MODULE_INDEX));
+    assert!(index.contains("pub mod a {") && index.contains("pub mod b {"));
+}
+
+#[test]
+fn cache_is_invalidated_by_a_change_to_an_imported_proto() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, third, out) = (
+        tmp.path().join("protos"),
+        tmp.path().join("third"),
+        tmp.path().join("out"),
+    );
+    write(
+        &source.join("m.proto"),
+        proto("m", "import \"ext/e.proto\";\nmessage M { ext.E e = 1; }"),
+    );
+    let imported = third.join("ext/e.proto");
+    write(&imported, proto("ext", "enum E { E_UNSPECIFIED = 0; }"));
+    let run = || {
+        builder(&source, &out)
+            .include_path(&third)
+            .cache(true)
+            .run()
+            .unwrap()
+    };
+
+    run();
+    assert!(read(&out.join("m.rs")).contains("pub e: i32"));
+    write(&imported, proto("ext", "message E {}"));
+    run();
+    assert!(read(&out.join("m.rs")).contains("pub e: ::core::option::Option<super::ext::E>"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_back_into_the_source_tree_compiles_each_file_once() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(&source.join("a/x.proto"), proto("a", "message X {}"));
+    std::os::unix::fs::symlink(source.join("a"), source.join("alias")).unwrap();
+
+    // Listing the source as an include path as well overlaps it a second way.
+    let manifest = builder(&source, &out).include_path(&source).run().unwrap();
+
+    assert_eq!(manifest.modules, ["a"]);
+    assert!(read(&out.join("a.rs")).contains("pub struct X"));
+}
+
+#[test]
+fn incremental_compile_regenerates_only_a_changed_leaf_and_its_dependents() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    let leaf = source.join("leaf/l.proto");
+    write(&leaf, proto("leaf", "message L {}"));
+    write(
+        &source.join("mid/m.proto"),
+        proto(
+            "mid",
+            "import \"leaf/l.proto\";\nmessage M { leaf.L l = 1; }",
+        ),
+    );
+    write(
+        &source.join("other/o.proto"),
+        proto("other", "message O {}"),
+    );
+    let run = || builder(&source, &out).incremental(true).run().unwrap();
+
+    run();
+    // Tag every output, so a rewrite by the next compile shows up as a lost tag.
+    for module in ["leaf", "mid", "other"] {
+        let path = out.join(format!("{module}.rs"));
+        write(&path, read(&path) + "// tagged\n");
+    }
+    write(&leaf, proto("leaf", "message L { int32 n = 1; }"));
+    run();
+
+    assert!(read(&out.join("leaf.rs")).contains("pub n: i32"));
+    assert!(!read(&out.join("mid.rs")).contains("// tagged"));
+    assert!(read(&out.join("other.rs")).contains("// tagged"));
+}
+
+#[test]
+fn rustfmt_output_is_stable_across_runs() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("f.proto"),
+        proto(
+            "f",
+            "message F { map<string, int32> counts = 1; oneof pick { string a = 2; }}",
+        ),
+    );
+    let rustfmt = std::env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let run = || {
+        builder(&source, &out).rustfmt(&rustfmt).run().unwrap();
+        read(&out.join("f.rs"))
+    };
+
+    let first = run();
+    assert_eq!(run(), first);
+    // Without a working rustfmt the pass is skipped, and stability is all there is to check.
+    let check = std::process::Command::new(&rustfmt)
+        .args(["--edition", "2021", "--check"])
+        .arg(out.join("f.rs"))
+        .status();
+    if let Ok(status) = check {
+        assert!(status.success(), "f.rs is not rustfmt-clean");
+    }
+}
+
+#[test]
+fn bom_prefixed_proto_is_staged_verbatim_and_compiles() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, staging, out) = (
+        tmp.path().join("protos"),
+        tmp.path().join("staging"),
+        tmp.path().join("out"),
+    );
+    let bom_proto = [&b"\xEF\xBB\xBF"[..], proto("b", "message B {}").as_bytes()].concat();
+    write(&source.join("b.proto"), &bom_proto);
+
+    let manifest = builder(&source, &out).staging_dir(&staging).run().unwrap();
+
+    assert_eq!(manifest.modules, ["b"]);
+    assert_eq!(std::fs::read(staging.join("b.proto")).unwrap(), bom_proto);
+}
+
+#[test]
+fn binary_proto_is_reported_by_name() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(&source.join("ok.proto"), proto("ok", "message O {}"));
+    write(&source.join("bad.proto"), [0xff, 0xfe, 0x00, 0x9f]);
+
+    match builder(&source, &out).run() {
+        Err(BuildError::Invalid(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].file.ends_with("bad.proto"));
+            assert_eq!(errors[0].reason, "is not valid UTF-8 text");
+        }
+        other => panic!("expected the binary proto to be rejected, got {other:?}"),
+    }
+}
+
+#[test]
+fn two_level_prefix_is_stripped_and_cross_imports_follow() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, staging, out) = (
+        tmp.path().join("protos"),
+        tmp.path().join("staging"),
+        tmp.path().join("out"),
+    );
+    write(
+        &source.join("com/acme/a/x.proto"),
+        proto("a", "message X {}"),
+    );
+    write(
+        &source.join("com/acme/b/y.proto"),
+        proto(
+            "b",
+            "import \"com/acme/a/x.proto\";\nimport \"google/protobuf/empty.proto\";\n\
+             message Y { a.X x = 1; google.protobuf.Empty e = 2; }",
+        ),
+    );
+
+    let manifest = builder(&source, &out)
+        .staging_dir(&staging)
+        .strip_prefix("com/acme")
+        .run()
+        .unwrap();
+
+    assert_eq!(manifest.modules, ["a", "b"]);
+    assert!(staging.join("a/x.proto").is_file());
+    let staged = read(&staging.join("b/y.proto"));
+    assert!(staged.contains("import \"a/x.proto\";"));
+    assert!(staged.contains("import \"google/protobuf/empty.proto\";"));
+    assert!(read(&out.join("b.rs")).contains("super::a::X"));
+}
+
+#[test]
+fn sibling_configs_give_each_proto_its_own_attributes() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    let attribute =
+        |attribute: &str| format!("[[type_attribute]]\npath = \".\"\nattribute = {attribute:?}\n");
+    write(&source.join("a.proto"), proto("p", "message A {}"));
+    write(
+        &source.join("a.proto.gen.toml"),
+        attribute("#[derive(Eq, Hash)]"),
+    );
+    write(&source.join("b.proto"), proto("p", "message B {}"));
+    write(
+        &source.join("b.proto.gen.toml"),
+        attribute("#[non_exhaustive]"),
+    );
+
+    builder(&source, &out).run().unwrap();
+
+    // Attributes sit above the struct they belong to, and A is generated before B.
+    let generated = read(&out.join("p.rs"));
+    let (a, rest) = generated.split_once("pub struct A").unwrap();
+    let (b, _) = rest.split_once("pub struct B").unwrap();
+    assert!(a.contains("#[derive(Eq, Hash)]") && !a.contains("#[non_exhaustive]"));
+    assert!(b.contains("#[non_exhaustive]") && !b.contains("#[derive(Eq, Hash)]"));
+}
+
+#[test]
+fn multi_line_comment_becomes_doc_comments_with_its_fence_closed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("d.proto"),
+        proto(
+            "d",
+            "// A user of the service.\n// Looked up by id, for example:\n// ```\n// get(42)\n\
+             message User {}",
+        ),
+    );
+
+    builder(&source, &out).run().unwrap();
+    let generated = read(&out.join("d.rs"));
+    let docs: Vec<&str> = generated
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with("#["))
+        .filter(|line| line.starts_with("///"))
+        .collect();
+    assert_eq!(
+        docs[..2],
+        [
+            "/// A user of the service.",
+            "/// Looked up by id, for example:"
+        ]
+    );
+    // The unclosed fence is closed, and marked as text so rustdoc doesn't run it.
+    let fences: Vec<&str> = docs
+        .iter()
+        .copied()
+        .filter(|line| line.starts_with("/// ```"))
+        .collect();
+    assert_eq!(fences.len(), 2, "{docs:?}");
+    assert!(fences[0].starts_with("/// ```text"), "{docs:?}");
+
+    builder(&source, &out).doc_comments(false).run().unwrap();
+    assert!(!read(&out.join("d.rs")).contains("///"));
+}
+
+#[test]
+fn descriptor_set_describes_the_compiled_protos() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("s.proto"),
+        proto("s", "service S { rpc Get(G) returns (G); }\nmessage G {}"),
+    );
+    let descriptor_set = out.join("descriptors.bin");
+
+    let manifest = builder(&source, &out)
+        .descriptor_set(&descriptor_set)
+        .run()
+        .unwrap();
+
+    assert_eq!(manifest.descriptor_set, Some(descriptor_set.clone()));
+    let bytes = std::fs::read(&descriptor_set).unwrap();
+    assert!(!bytes.is_empty());
+    let set = <prost_types::FileDescriptorSet as prost::Message>::decode(&bytes[..]).unwrap();
+    assert_eq!(set.file.len(), 1);
+    assert_eq!(set.file[0].package(), "s");
+    assert_eq!(set.file[0].service[0].name(), "S");
+}
+
+#[test]
+fn proto3_optional_scalar_becomes_an_option() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("o.proto"),
+        proto("o", "message O { optional int32 n = 1; int32 m = 2; }"),
+    );
+
+    for compat in [false, true] {
+        builder(&source, &out)
+            .proto3_optional(compat)
+            .run()
+            .unwrap();
+        let generated = read(&out.join("o.rs"));
+        assert!(generated.contains("pub n: ::core::option::Option<i32>"));
+        assert!(generated.contains("pub m: i32"));
+    }
+}
+
+#[test]
+fn package_with_a_trailing_comment_or_a_commented_out_one_compiles() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("a.proto"),
+        "syntax = \"proto3\";\npackage foo.bar; // the bar API\nmessage A {}\n",
+    );
+    write(
+        &source.join("b.proto"),
+        "syntax = \"proto3\";\n/* Was:\npackage old;\n*/\npackage baz;\nmessage B {}\n",
+    );
+
+    let manifest = builder(&source, &out).run().unwrap();
+
+    assert_eq!(manifest.modules, ["baz", "foo"]);
+    assert!(read(&out.join("foo.bar.rs")).contains("pub struct A"));
+}
+
+#[test]
+fn messages_only_skips_a_package_that_only_declares_services() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(&source.join("m.proto"), proto("m", "message Req {}"));
+    write(
+        &source.join("svc.proto"),
+        proto(
+            "svc",
+            "import \"m.proto\";\nservice Api { rpc Call(m.Req) returns (m.Req); }",
+        ),
+    );
+
+    let manifest = builder(&source, &out).messages_only(true).run().unwrap();
+
+    assert_eq!(manifest.modules, ["m"]);
+    assert!(!out.join("svc.rs").exists());
+}
+
+#[test]
+fn messages_only_generates_the_same_messages_as_tonic() {
+    let tmp = tempfile::tempdir().unwrap();
+    let source = tmp.path().join("protos");
+    write(
+        &source.join("g.proto"),
+        proto(
+            "g",
+            "// A greeting.\nmessage Hello { string name = 1; repeated int32 ids = 2; }\n\
+             enum Mood { MOOD_UNSPECIFIED = 0; MOOD_GLAD = 1; }",
+        ),
+    );
+    let generate = |messages_only: bool, out: &str| {
+        let out = tmp.path().join(out);
+        builder(&source, &out)
+            .messages_only(messages_only)
+            .run()
+            .unwrap();
+        std::fs::read(out.join("g.rs")).unwrap()
+    };
+
+    assert_eq!(generate(true, "prost"), generate(false, "tonic"));
+}
+
+#[test]
+fn protoc_is_only_checked_when_something_is_compiled() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, vendor) = (tmp.path().join("protos"), tmp.path().join("vendor"));
+    write(&source.join("v.proto"), proto("v", "message V {}"));
+    let future: proto_gen::ProtocVersion = "99.0.0".parse().unwrap();
+
+    builder(&source, &vendor).vendor(true).run().unwrap();
+    // Current vendored code needs no protoc at all, let alone a newer one.
+    builder(&source, &vendor)
+        .vendor(true)
+        .min_protoc_version(future)
+        .run()
+        .unwrap();
+
+    write(
+        &source.join("v.proto"),
+        proto("v", "message V { int32 n = 1; }"),
+    );
+    let err = builder(&source, &vendor)
+        .vendor(true)
+        .min_protoc_version(future)
+        .run()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("older than the required 99.0.0"),
+        "{err}"
+    );
+}
+
+#[test]
+fn check_reports_an_edited_vendor_file_and_leaves_it_alone() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, vendor) = (tmp.path().join("protos"), tmp.path().join("vendor"));
+    write(
+        &source.join("v.proto"),
+        proto("v", "message V { int32 n = 1; }"),
+    );
+    builder(&source, &vendor).vendor(true).run().unwrap();
+    // Where check() regenerates, as tests run without Cargo's OUT_DIR.
+    let scratch = std::env::var_os("OUT_DIR")
+        .map_or_else(std::env::temp_dir, Into::into)
+        .join(format!("proto-gen-check-{}", std::process::id()));
+
+    builder(&source, &vendor).vendor(true).check().unwrap();
+    assert!(!scratch.exists());
+
+    let vendored = vendor.join("v.rs");
+    let edited = read(&vendored) + "// edited by hand\n";
+    write(&vendored, &edited);
+    let err = builder(&source, &vendor).vendor(true).check().unwrap_err();
+
+    match err {
+        BuildError::VendorOutdated(dir, differences) => {
+            assert_eq!(dir, vendor);
+            assert_eq!(differences.len(), 1, "{differences:?}");
+            assert!(differences[0].contains("`v.rs` differs"), "{differences:?}");
+        }
+        err => panic!("expected VendorOutdated, got {err}"),
+    }
+    assert!(!scratch.exists());
+    assert_eq!(read(&vendored), edited);
+}
+
+/// Compiles a message with a `google.protobuf.Timestamp` field under `mode`, returning its
+/// generated code and the output directory.
+fn compile_timestamp(mode: WellKnownTypes) -> (String, tempfile::TempDir) {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, out) = (tmp.path().join("protos"), tmp.path().join("out"));
+    write(
+        &source.join("event.proto"),
+        proto(
+            "event",
+            "import \"google/protobuf/timestamp.proto\";\n\
+             message Event { google.protobuf.Timestamp at = 1; }",
+        ),
+    );
+
+    let manifest = builder(&source, &out).well_known_types(mode).run().unwrap();
+
+    // The well-known type comes from outside, never from a generated google.protobuf module.
+    assert_eq!(manifest.modules, ["event"]);
+    assert!(!out.join("google.protobuf.rs").exists());
+    (read(&out.join("event.rs")), tmp)
+}
+
+#[test]
+fn default_well_known_types_use_prost_types() {
+    let (code, tmp) = compile_timestamp(WellKnownTypes::Default);
+    assert!(code.contains("pub at: ::core::option::Option<::prost_types::Timestamp>"));
+    assert!(!tmp.path().join("out").join(proto_gen::NATIVE_SHIM).exists());
+}
+
+#[test]
+fn prost_types_well_known_types_use_prost_types() {
+    let (code, tmp) = compile_timestamp(WellKnownTypes::ProstTypes);
+    assert!(code.contains("pub at: ::core::option::Option<::prost_types::Timestamp>"));
+    assert!(!tmp.path().join("out").join(proto_gen::NATIVE_SHIM).exists());
+}
+
+#[test]
+fn native_well_known_types_use_the_shim_written_next_to_the_code() {
+    let (code, tmp) = compile_timestamp(WellKnownTypes::Native {
+        module: "crate::wkt".to_string(),
+    });
+    assert!(code.contains("pub at: ::core::option::Option<crate::wkt::Timestamp>"));
+    let shim = read(&tmp.path().join("out").join(proto_gen::NATIVE_SHIM));
+    assert!(shim.contains("pub struct Timestamp(pub ::std::time::SystemTime);"));
+}
+
+#[test]
+fn well_known_type_resolves_from_a_vendored_include_dir() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (source, vendored, out) = (
+        tmp.path().join("protos"),
+        tmp.path().join("third_party"),
+        tmp.path().join("out"),
+    );
+    // protoc falls back to its own copy of the well-known types, so the vendored one carries
+    // a message only it declares.
+    write(
+        &vendored.join("google/protobuf/timestamp.proto"),
+        proto(
+            "google.protobuf",
+            "message Timestamp { int64 seconds = 1; int32 nanos = 2; }\n\
+             message VendoredOnly {}",
+        ),
+    );
+    write(
+        &source.join("event.proto"),
+        proto(
+            "event",
+            "import \"google/protobuf/timestamp.proto\";\n\
+             message Event { google.protobuf.Timestamp at = 1; google.protobuf.VendoredOnly v = 2; }",
+        ),
+    );
+
+    let manifest = builder(&source, &out)
+        .include_path(&vendored)
+        .run()
+        .unwrap();
+
+    assert_eq!(manifest.modules, ["event"]);
+    let code = read(&out.join("event.rs"));
+    assert!(code.contains("pub at: ::core::option::Option<::prost_types::Timestamp>"));
+    assert!(code.contains("pub v: ::core::option::Option<::prost_types::VendoredOnly>"));
+}